@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use program::guest::{handle_tx_set, DEFAULT_MAX_ENTRIES};
+
+// First byte is the expected network, the rest is a tx set as the host
+// would pass it: proof type || expected root || subtree || transaction
+fuzz_target!(|data: &[u8]| {
+    if let Some((network, input)) = data.split_first() {
+        let _ = handle_tx_set(*network, DEFAULT_MAX_ENTRIES, 0, input.to_vec());
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use program::TransactionReader;
+
+// The reader must never panic on arbitrary bytes, only report errors
+fuzz_target!(|data: &[u8]| {
+    let reader = match TransactionReader::try_new(data) {
+        Ok(reader) => reader,
+        Err(_) => return,
+    };
+    let _ = (reader.header(), reader.version(), reader.network(), reader.space_hash());
+
+    for entry in reader.entries().flatten() {
+        let _ = (entry.subspace_hash(), entry.owner(), entry.witness());
+    }
+    for _ in reader.iter() {}
+});
@@ -0,0 +1,41 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use program::guest::{encode_tx_set, handle_tx_set, DEFAULT_MAX_ENTRIES, PROOF_TYPE_STANDARD};
+use program::{HEADER_SIZE, SUBSPACE_HASH_SIZE};
+use spacedb::Sha256Hasher;
+use spacedb::subtree::{SubTree, ValueOrHash};
+
+const KEY: [u8; SUBSPACE_HASH_SIZE] = [1u8; SUBSPACE_HASH_SIZE];
+
+// An existing leaf with an arbitrary value and an arbitrary entry updating it.
+// The first byte splits the rest into the leaf value and the entry's owner and
+// witness, owners of the wrong length on either side must be errors, not panics
+fuzz_target!(|data: &[u8]| {
+    let (split, data) = match data.split_first() {
+        Some((split, data)) => ((*split as usize).min(data.len()), data),
+        None => return,
+    };
+    let (leaf, entry) = data.split_at(split);
+    if SUBSPACE_HASH_SIZE + entry.len() > u16::MAX as usize {
+        return;
+    }
+
+    let mut subtree = SubTree::<Sha256Hasher>::empty();
+    if subtree.insert(KEY, ValueOrHash::Value(leaf.to_vec())).is_err() {
+        return;
+    }
+    let root = match subtree.root() {
+        Ok(root) => root,
+        Err(_) => return,
+    };
+
+    let mut tx = vec![0u8; HEADER_SIZE];
+    tx.extend_from_slice(&((SUBSPACE_HASH_SIZE + entry.len()) as u16).to_le_bytes());
+    tx.extend_from_slice(&KEY);
+    tx.extend_from_slice(entry);
+
+    if let Ok(input) = encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, &tx) {
+        let _ = handle_tx_set(0, DEFAULT_MAX_ENTRIES, 0, input);
+    }
+});
@@ -10,8 +10,11 @@ use program::guest::{run};
 risc0_zkvm::guest::entry!(main);
 
 pub fn main() {
+    let network: u8 = env::read();
+    let max_entries: u32 = env::read();
+    let height: u32 = env::read();
     let payload: Vec<Vec<u8>> = env::read();
-    let out = match run(payload) {
+    let out = match run(network, max_entries, height, payload) {
         Ok(out) => out,
         Err(e) => panic!("{}", e),
     };
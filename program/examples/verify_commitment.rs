@@ -0,0 +1,93 @@
+//! Checks a commitment by re-running the guest logic natively, no prover needed.
+//!
+//! A light client holding a space's subtree and transaction can use the same
+//! steps to confirm the roots a receipt's journal claims. The claimed
+//! commitment comes from elsewhere, here the "registrations" test vector of
+//! the zero seed, which registers alice and bob into an empty space.
+//!
+//! $ subs test-vectors | jq '.[0].commitment' | cargo run --package program --example verify_commitment
+
+use std::io::Read;
+
+use program::builder::{OwnerPublicKey, Transaction, TransactionBuilder};
+use program::guest::{encode_tx_set, verify_tx_set, Commitment, GuestError, PROOF_TYPE_STANDARD};
+use program::vectors;
+use spacedb::{Sha256Hasher, subtree::SubTree};
+
+fn main() {
+    let mut json = String::new();
+    std::io::stdin().read_to_string(&mut json).expect("read commitment from stdin");
+    let claimed = parse_commitment(&json).unwrap_or_else(|e| {
+        eprintln!("Invalid commitment: {}", e);
+        std::process::exit(2);
+    });
+
+    // The same transaction the vector was built from
+    let alice = vectors::derive_key(&[0u8; 32], 0);
+    let bob = vectors::derive_key(&[0u8; 32], 1);
+    let mut builder = TransactionBuilder::new();
+    builder.add(Transaction::new("alice", alice.owner_public_key()), None)
+        .expect("add registration");
+    builder.add(Transaction::new("bob", bob.owner_public_key()), None)
+        .expect("add registration");
+    let tx = builder.build("example").expect("build transaction");
+
+    // Registering into a fresh space, so the subtree starts out empty
+    let subtree: SubTree<Sha256Hasher> = SubTree::empty();
+    let root = subtree.root().expect("subtree root");
+    let input = encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, &tx)
+        .expect("encode tx set");
+
+    let verify = |claimed: &Commitment| {
+        verify_tx_set(claimed.network, claimed.max_entries, claimed.height, input.clone(), claimed)
+    };
+    match verify(&claimed) {
+        Ok(()) => {
+            println!("Commitment verified");
+            println!("- Space: {}", hex::encode(claimed.space));
+            println!("- Initial: {}", hex::encode(claimed.initial_root));
+            println!("- Final: {}", hex::encode(claimed.final_root));
+        }
+        Err(e) => {
+            eprintln!("Commitment rejected: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // A journal claiming any other final root for the same input is refused
+    let mut tampered = claimed.clone();
+    tampered.final_root[0] ^= 1;
+    match verify(&tampered) {
+        Err(GuestError::CommitmentMismatch) => {
+            println!("Tampered commitment rejected");
+            println!("- Final: {}", hex::encode(tampered.final_root));
+        }
+        other => {
+            eprintln!("Tampered commitment not rejected: {:?}", other);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads a commitment in the form `subs test-vectors` prints it, roots and
+/// space in hex
+fn parse_commitment(json: &str) -> Result<Commitment, String> {
+    let value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    let hash = |field: &str| -> Result<[u8; 32], String> {
+        value[field].as_str()
+            .and_then(|s| hex::decode(s).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| format!("{} must be 32 bytes hex", field))
+    };
+    let number = |field: &str| -> Result<u64, String> {
+        value[field].as_u64().ok_or_else(|| format!("{} must be a number", field))
+    };
+    Ok(Commitment {
+        space: hash("space")?,
+        initial_root: hash("initial_root")?,
+        final_root: hash("final_root")?,
+        network: u8::try_from(number("network")?).map_err(|e| e.to_string())?,
+        max_entries: u32::try_from(number("max_entries")?).map_err(|e| e.to_string())?,
+        height: u32::try_from(number("height")?).map_err(|e| e.to_string())?,
+    })
+}
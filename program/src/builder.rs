@@ -1,68 +1,249 @@
 // Not part of the guest program
 
+use core::cmp::Ordering;
 use core::fmt;
 use std::collections::HashSet;
 
 use k256::ecdsa::signature::Signer;
 use k256::ecdsa::SigningKey;
-use serde::{Serialize, Deserialize};
+use k256::schnorr;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
 use serde::de::{Error};
 
-use serde_with::{serde_as};
+use serde_with::{serde_as, DeserializeAs, SerializeAs};
 use serde_with::base64::{Base64};
 use serde_with::hex::Hex;
 use sha2::{Sha256, Digest};
-use crate::{HEADER_SIZE};
+use hkdf::Hkdf;
+use crate::{
+    Entry, ENTRY_FIXED_SIZE, FORMAT_VERSION, HEADER_SIZE, LENGTH_PREFIX_SIZE, Network, NETWORK_OFFSET, Owner,
+    OWNER_SIZE, SEC1_ODD_TAG, SEC1_OWNER_SIZE, SPACE_HASH_OFFSET, SubspaceHash,
+};
+use crate::guest::{
+    handle_transition, owner_key, owner_value, parse_witness, signed_message, transfer_preimage, ValidityWindow,
+    MEMO_LENGTH_SIZE, WINDOW_SIZE, WITNESS_FLAG_MEMO, WITNESS_FLAG_WINDOW, WITNESS_TYPE_NONE, WITNESS_TYPE_ROTATION,
+    WITNESS_TYPE_SCHNORR, WITNESS_TYPE_SIGNATURE,
+};
+
+/// Largest memo a transaction may carry
+pub const MAX_MEMO_SIZE: usize = 256;
 
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[derive(PartialEq)]
+// JSON keys are camelCase for JS clients, today every key is a single word so
+// this only pins the convention for fields added later
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct TransactionBuilder {
     version: u8,
+    #[serde(default)]
+    network: u8,
+    /// Space the witnesses were signed for, set by the first signed entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    space: Option<String>,
     pub transactions: Vec<Transaction>,
+    /// Scheme new single-key witnesses are signed with, not saved
+    #[serde(skip)]
+    scheme: SignatureScheme,
+}
+
+/// Entries of a builder merged with `merge_changed`, by what happened to them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeDelta {
+    /// Names that weren't staged before
+    pub added: usize,
+    /// Staged names whose owner, witness or memo was replaced
+    pub changed: usize,
+    /// Staged names that were skipped, they're already staged as given
+    pub unchanged: usize,
+}
+
+/// Signature scheme of single-key witnesses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// ECDSA over SHA-256 of the message, `WITNESS_TYPE_SIGNATURE`
+    #[default]
+    Ecdsa,
+    /// BIP340 over SHA-256 of the message, `WITNESS_TYPE_SCHNORR`
+    Schnorr,
 }
 
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug)]
-#[derive(PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct Transaction {
     pub name: String,
 
-    #[serde_as(as = "Hex")]
-    pub owner: [u8; 32],
+    #[serde_as(as = "OwnerHex")]
+    pub owner: Owner,
 
     #[serde_as(as = "Base64")]
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub witness: Vec<u8>,
 
+    /// Opaque data covered by the witness and stored alongside the owner
+    #[serde_as(as = "Base64")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub memo: Vec<u8>,
+
+    /// Block heights the witness is valid at, covered by the witness
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window: Option<ValidityWindow>,
+
     #[serde(skip)]
-    key: [u8; 32],
+    key: SubspaceHash,
 }
 
 impl TransactionBuilder {
     pub fn new() -> Self {
+        Self::with_network(Network::Mainnet)
+    }
+
+    pub fn with_network(network: Network) -> Self {
         Self {
-            version: 0,
+            version: FORMAT_VERSION,
+            network: network as u8,
+            space: None,
             transactions: Vec::new(),
+            scheme: SignatureScheme::Ecdsa,
         }
     }
 
-    pub fn merge(&mut self, other: Self) -> Result<(), BuilderError> {
-        if self.version != other.version {
-            return Err(BuilderError(format!("versions do not match: {} != {}", self.version, other.version)));
+    /// Signs transfers and renewals added from now on with `scheme`.
+    /// Rotations are always ECDSA.
+    pub fn set_scheme(&mut self, scheme: SignatureScheme) {
+        self.scheme = scheme;
+    }
+
+    pub fn network(&self) -> Option<Network> {
+        Network::try_from(self.network).ok()
+    }
+
+    /// Space the builder's witnesses are signed for, if any are signed yet
+    pub fn space(&self) -> Option<&str> {
+        self.space.as_deref()
+    }
+
+    /// Ties the builder to `space`, signatures only verify under the space
+    /// they were made for so a builder can't be used with another one
+    fn bind_space(&mut self, space: &str) -> Result<(), BuilderError> {
+        match &self.space {
+            Some(bound) if bound != space => {
+                Err(BuilderError(format!("builder is for @{}, not @{}", bound, space)))
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.space = Some(String::from(space));
+                Ok(())
+            }
         }
+    }
+
+    /// Merges all entries of `other` into this builder. Either every entry
+    /// is added or, on error, the builder is left unchanged. If the versions
+    /// differ, the older builder is upgraded to the newer version first.
+    pub fn merge(&mut self, mut other: Self) -> Result<(), BuilderError> {
+        if self.network != other.network {
+            return Err(BuilderError(format!("networks do not match: {} != {}", self.network, other.network)));
+        }
+        if other.version < self.version {
+            other.upgrade(self.version)?;
+        }
+
+        // add only appends, so rolling back is truncating to where we started
+        let (version, space, snapshot) = (self.version, self.space.clone(), self.transactions.len());
+        if let Err(e) = self.merge_entries(other) {
+            self.version = version;
+            self.space = space;
+            self.transactions.truncate(snapshot);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Like `merge`, but entries staged before with the same owner, witness,
+    /// memo and validity window are skipped and entries staged with different ones are
+    /// replaced. Either every entry is merged or the builder is unchanged.
+    pub fn merge_changed(&mut self, mut other: Self) -> Result<MergeDelta, BuilderError> {
+        if self.network != other.network {
+            return Err(BuilderError(format!("networks do not match: {} != {}", self.network, other.network)));
+        }
+        if other.version < self.version {
+            other.upgrade(self.version)?;
+        }
+
+        // entries may be replaced in place, so roll back to a copy
+        let before = self.clone();
+        let result = self.merge_changed_entries(other);
+        if result.is_err() {
+            *self = before;
+        }
+        result
+    }
+
+    fn merge_changed_entries(&mut self, other: Self) -> Result<MergeDelta, BuilderError> {
+        self.upgrade(other.version)?;
+        if let Some(space) = &other.space {
+            self.bind_space(space)?;
+        }
+        let mut delta = MergeDelta::default();
         for entry in other.transactions {
+            match self.transactions.iter().position(|e| e.name == entry.name) {
+                Some(i) if self.transactions[i].owner == entry.owner
+                    && self.transactions[i].witness == entry.witness
+                    && self.transactions[i].memo == entry.memo
+                    && self.transactions[i].window == entry.window => {
+                    delta.unchanged += 1;
+                    continue;
+                }
+                Some(i) => {
+                    self.transactions.remove(i);
+                    delta.changed += 1;
+                }
+                None => delta.added += 1,
+            }
             self.add(entry, None)?;
         }
+        Ok(delta)
+    }
+
+    fn merge_entries(&mut self, other: Self) -> Result<(), BuilderError> {
+        self.upgrade(other.version)?;
+        if let Some(space) = &other.space {
+            self.bind_space(space)?;
+        }
+        for entry in other.transactions {
+            self.add(entry, None)?;
+        }
+        Ok(())
+    }
+
+    /// Brings the builder up to `version`, a builder can't be downgraded.
+    ///
+    /// Versions so far only differ in the header byte. Every witness signs
+    /// the header though, so signed entries can't be carried across and
+    /// have to be signed again at the new version. A version that changes
+    /// the entry layout converts entries here as well.
+    pub fn upgrade(&mut self, version: u8) -> Result<(), BuilderError> {
+        if version == self.version {
+            return Ok(());
+        }
+        if version < self.version {
+            return Err(BuilderError(format!("cannot downgrade from version {} to {}", self.version, version)));
+        }
+        if version > FORMAT_VERSION {
+            return Err(BuilderError(format!("unsupported version {}", version)));
+        }
+        if let Some(entry) = self.transactions.iter().find(|e| !e.witness.is_empty()) {
+            return Err(BuilderError(format!("{} is signed for version {} and must be signed again",
+                                            entry.name, self.version)));
+        }
+        self.version = version;
         Ok(())
     }
 
     fn make_header(&mut self, space: &str) -> [u8; HEADER_SIZE] {
-        let mut raw_header = [0u8; HEADER_SIZE];
-        raw_header[0] = self.version;
-        let space_hash = hash(space.as_bytes());
-        raw_header[1..].copy_from_slice(&space_hash);
-        return raw_header;
+        make_header(self.version, self.network, space)
     }
 
     pub fn to_json(&self) -> serde_json::Result<String> {
@@ -83,51 +264,129 @@ impl TransactionBuilder {
         Ok(s)
     }
 
-    pub fn add(&mut self, mut entry: Transaction, key: Option<(&str, SigningKey)>) -> Result<(), BuilderError> {
+    pub fn add(&mut self, entry: Transaction, key: Option<(&str, SigningKey)>) -> Result<(), BuilderError> {
+        self.insert(entry, key, false)
+    }
+
+    /// Adds a signed renewal, unlike a transfer the owner is allowed to stay the same
+    pub fn renew(&mut self, entry: Transaction, key: (&str, SigningKey)) -> Result<(), BuilderError> {
+        self.insert(entry, Some(key), true)
+    }
+
+    fn insert(&mut self, mut entry: Transaction, key: Option<(&str, SigningKey)>, renewal: bool)
+        -> Result<(), BuilderError> {
         if self.transactions.iter().any(|e| e.name == entry.name) {
             return Err(BuilderError(format!("duplicate name: {}", entry.name)));
         }
+        // not a valid curve point, the name would be stuck forever
+        if entry.owner == Owner::default() {
+            return Err(BuilderError(format!("owner of {} must not be all zeros", entry.name)));
+        }
+        if entry.memo.len() > MAX_MEMO_SIZE {
+            return Err(BuilderError(format!("memo for {} exceeds {} bytes", entry.name, MAX_MEMO_SIZE)));
+        }
+        if entry.window.is_some_and(|w| w.valid_from > w.valid_until) {
+            return Err(BuilderError(format!("validity window of {} ends before it starts", entry.name)));
+        }
 
         if key.is_some() {
             let (space, key) = key.unwrap();
-            let header = self.make_header(space);
+            if !renewal && key.owner_public_key() == entry.owner {
+                return Err(BuilderError(format!("{} is already owned by this key", entry.name)));
+            }
+            self.bind_space(space)?;
+            let msg = self.signing_message(space, &entry);
+            let signing_key = key.owner_signing_key();
+            match self.scheme {
+                SignatureScheme::Ecdsa => {
+                    let (sig, _) = signing_key.sign(&msg);
+                    entry.witness.push(WITNESS_TYPE_SIGNATURE);
+                    entry.witness.extend_from_slice(sig.to_bytes().as_slice());
+                }
+                SignatureScheme::Schnorr => {
+                    let sig = schnorr::SigningKey::from(*signing_key.as_nonzero_scalar())
+                        .try_sign(&msg)
+                        .map_err(|_| BuilderError(format!("could not sign {}", entry.name)))?;
+                    entry.witness.push(WITNESS_TYPE_SCHNORR);
+                    entry.witness.extend_from_slice(&sig.to_bytes());
+                }
+            }
+        }
 
-            let mut msg = [0u8; HEADER_SIZE + 64];
-            msg[..HEADER_SIZE].copy_from_slice(&header);
-            let h = hash(entry.name.as_bytes());
-            msg[HEADER_SIZE..HEADER_SIZE + 32].copy_from_slice(&h);
-            msg[HEADER_SIZE + 32..HEADER_SIZE + 64].copy_from_slice(&entry.owner);
+        self.transactions.push(entry);
+        Ok(())
+    }
 
-            let (sig, _) = key.sign(&msg);
-            entry.witness.push(0x00);
-            entry.witness.extend_from_slice(sig.to_bytes().as_slice());
+    /// Adds a key rotation of `name` from `old_key` to `new_key`. Both keys sign
+    /// so the new owner proves it holds the key it's being handed.
+    pub fn rotate(&mut self, name: &str, space: &str, old_key: SigningKey, new_key: SigningKey)
+        -> Result<(), BuilderError> {
+        if self.transactions.iter().any(|e| e.name == name) {
+            return Err(BuilderError(format!("duplicate name: {}", name)));
         }
+        if old_key.owner_public_key() == new_key.owner_public_key() {
+            return Err(BuilderError(format!("{} is already owned by this key", name)));
+        }
+        self.bind_space(space)?;
+
+        let mut entry = Transaction::new(name, new_key.owner_public_key());
+        let msg = self.signing_message(space, &entry);
+        let (old_sig, _) = old_key.owner_signing_key().sign(&msg);
+        let (new_sig, _) = new_key.owner_signing_key().sign(&msg);
+        entry.witness.push(WITNESS_TYPE_ROTATION);
+        entry.witness.extend_from_slice(old_sig.to_bytes().as_slice());
+        entry.witness.extend_from_slice(new_sig.to_bytes().as_slice());
 
         self.transactions.push(entry);
         Ok(())
     }
 
+    /// Message the witness for `name` has to sign, so it can be signed on
+    /// another machine and attached with `attach_witness`
+    pub fn signing_preimage(&mut self, space: &str, name: &str) -> Result<Vec<u8>, BuilderError> {
+        let entry = self.transactions.iter().find(|e| e.name == name).cloned()
+            .ok_or_else(|| BuilderError(format!("no transaction for {}", name)))?;
+        Ok(self.signing_message(space, &entry))
+    }
+
+    /// Sets the witness of `name` to one computed elsewhere. It is checked
+    /// against `current_owner` first and the builder is left unchanged if
+    /// it doesn't verify.
+    pub fn attach_witness(&mut self, space: &str, name: &str, witness: Vec<u8>,
+                          current_owner: &Owner) -> Result<(), BuilderError> {
+        let network = self.network()
+            .ok_or_else(|| BuilderError(format!("unknown network {}", self.network)))?;
+        let index = self.transactions.iter().position(|e| e.name == name)
+            .ok_or_else(|| BuilderError(format!("no transaction for {}", name)))?;
+        let mut signed = self.transactions[index].clone();
+        signed.witness = witness;
+        signed.verify(space, network, Some(current_owner))?;
+        self.bind_space(space)?;
+        self.transactions[index] = signed;
+        Ok(())
+    }
+
+    fn signing_message(&mut self, space: &str, entry: &Transaction) -> Vec<u8> {
+        let preimage = transfer_preimage(&self.make_header(space), &entry.sort_key(), &entry.owner);
+        signed_message(&preimage, entry.window.as_ref(), &entry.memo)
+    }
+
     fn sort(&mut self) {
         for entry in self.transactions.iter_mut() {
-            entry.key = hash(entry.name.as_bytes());
-        }
-        self.transactions.sort_by(|a, b| {
-            // sort all non-empty witnesses to the front then sort by hash
-            match (a.witness.is_empty(), b.witness.is_empty()) {
-                (true, false) => std::cmp::Ordering::Greater,
-                (false, true) => std::cmp::Ordering::Less,
-                _ => a.key.cmp(&b.key)
-            }
-        });
+            entry.key = entry.sort_key();
+        }
+        // updates and registrations are interleaved by key, the guest
+        // merge-walks them against the subtree leaves, same order as `canonical_cmp`
+        self.transactions.sort_by_key(|e| e.key);
     }
 
     /// Builds a transaction with a given space and a list of updates.
     /// The transaction format is structured as follows:
     ///
     /// Header:
-    /// +------------------+---------------------+
-    /// | 1-byte version |   32-byte space hash  |
-    /// +------------------+---------------------+
+    /// +----------------+----------------+-----------------------+
+    /// | 1-byte version | 1-byte network |   32-byte space hash  |
+    /// +----------------+----------------+-----------------------+
     ///
     /// List of updates:
     /// +--------------+--------+----------+
@@ -141,60 +400,295 @@ impl TransactionBuilder {
     ///
     /// This function compiles the transaction bytes by following this structure.
     pub fn build(mut self, space: &str) -> Result<Vec<u8>, BuilderError> {
+        self.bind_space(space)?;
         let mut buffer = Vec::new();
-        buffer.push(self.version); // 1-byte version
-        let space_hash = hash(space.as_bytes()); // 32-byte space hash
-        buffer.extend_from_slice(&space_hash);
+        let header = self.make_header(space);
+        buffer.extend_from_slice(&header);
         self.sort();
 
+        // Names are unique, but two of them sharing a hash would reach the
+        // guest as the same key twice
+        if let Some(pair) = self.transactions.windows(2).find(|pair| pair[0].key == pair[1].key) {
+            return Err(BuilderError(format!("hash collision: {} and {} have the same subspace hash",
+                                            pair[0].name, pair[1].name)));
+        }
+
         for tx in &self.transactions {
-            self.write_tx(&mut buffer, tx);
+            self.write_tx(&mut buffer, tx)?;
         }
 
         Ok(buffer)
     }
 
-    fn write_tx(&self, buffer: &mut Vec<u8>, tx: &Transaction) {
-        // 2 bytes length + 32 bytes subspace hash + 32 bytes owner + witness length
-        let len = 32 + 32 + tx.witness.len();
+    /// Number of bytes `build` produces, computed from the layout without
+    /// building anything. The space doesn't matter, its hash is fixed size.
+    pub fn estimated_size(&self) -> usize {
+        HEADER_SIZE + self.transactions.iter()
+            .map(|tx| LENGTH_PREFIX_SIZE + ENTRY_FIXED_SIZE + tx.wire_witness_len())
+            .sum::<usize>()
+    }
+
+    fn write_tx(&self, buffer: &mut Vec<u8>, tx: &Transaction) -> Result<(), BuilderError> {
+        // the guest rejects these, don't build something that can't be proven
+        if tx.sort_key().is_reserved() {
+            return Err(BuilderError(format!("{} hashes to a reserved subspace hash", tx.name)));
+        }
+        let witness = tx.wire_witness();
+        // subspace hash + owner + witness, not counting the length prefix itself
+        let len = ENTRY_FIXED_SIZE + witness.len();
         let length_bytes = (len as u16).to_le_bytes();
         buffer.extend_from_slice(&length_bytes);
 
         // Write the subspace hash (32 bytes)
-        let subspace = hash(tx.name.as_bytes());
-        buffer.extend_from_slice(&subspace);
+        buffer.extend_from_slice(tx.sort_key().as_bytes());
 
         // Write the owner (32 bytes)
-        buffer.extend_from_slice(&tx.owner);
+        buffer.extend_from_slice(tx.owner.as_bytes());
 
         // Write the witness data
-        buffer.extend_from_slice(&tx.witness);
+        buffer.extend_from_slice(&witness);
+        Ok(())
+    }
+}
+
+/// Hex encoded owner that refuses an all-zero value, which is almost
+/// certainly a mistake in hand written JSON
+struct OwnerHex;
+
+impl SerializeAs<Owner> for OwnerHex {
+    fn serialize_as<S: Serializer>(source: &Owner, serializer: S) -> Result<S::Ok, S::Error> {
+        <Hex as SerializeAs<[u8; OWNER_SIZE]>>::serialize_as(source.as_bytes(), serializer)
+    }
+}
+
+impl<'de> DeserializeAs<'de, Owner> for OwnerHex {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Owner, D::Error> {
+        // either x-only or a compressed SEC1 key
+        let raw = <Hex as DeserializeAs<Vec<u8>>>::deserialize_as(deserializer)?;
+        let owner = match raw.len() {
+            SEC1_OWNER_SIZE => Owner::from_sec1(&raw)
+                .ok_or_else(|| D::Error::custom("owner is not a compressed SEC1 key"))?,
+            _ => Owner::try_from(raw.as_slice())
+                .map_err(|_| D::Error::custom("owner must be 32 or 33 bytes"))?,
+        };
+        if owner == Owner::default() {
+            return Err(D::Error::custom("owner must not be all zeros"));
+        }
+        Ok(owner)
+    }
+}
+
+impl Default for TransactionBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `key` only caches the hash of the name and isn't filled in when parsed
+impl PartialEq for Transaction {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.owner == other.owner
+            && self.witness == other.witness
+            && self.memo == other.memo
+            && self.window == other.window
     }
 }
 
 impl Transaction {
-    pub fn new(name: &str, owner: [u8; 32]) -> Self {
+    pub fn new(name: &str, owner: Owner) -> Self {
         Self {
             name: String::from(name),
             owner,
             witness: Vec::with_capacity(65),
-            key: hash(name.as_bytes()),
+            memo: Vec::new(),
+            window: None,
+            key: SubspaceHash(hash(name.as_bytes())),
+        }
+    }
+
+    /// Rebuilds the transaction an entry of a built transaction came from.
+    /// Only the subspace hash is on the wire, so the name has to be given
+    /// and must hash to it. Building it again gives the same entry.
+    pub fn from_entry(entry: &Entry, name: &str) -> Result<Self, BuilderError> {
+        let mut tx = Self::new(name, *entry.owner());
+        if tx.key != *entry.subspace_hash() {
+            return Err(BuilderError(format!("{} does not hash to subspace {}",
+                                            name, hex::encode(entry.subspace_hash()))));
+        }
+        let witness = parse_witness(entry.witness())
+            .map_err(|e| BuilderError(format!("{}: {}", name, e)))?;
+        if witness.memo.is_empty() && witness.window.is_none() {
+            tx.witness = entry.witness().to_vec();
+            return Ok(tx);
+        }
+        // the type byte of a memo on its own is added back by wire_witness
+        if witness.kind != WITNESS_TYPE_NONE || !witness.signatures.is_empty() {
+            tx.witness.push(witness.kind);
+            tx.witness.extend_from_slice(witness.signatures);
+        }
+        tx.memo = witness.memo.to_vec();
+        tx.window = witness.window;
+        Ok(tx)
+    }
+
+    /// Attaches a memo, it must be set before the transaction is signed
+    pub fn with_memo(mut self, memo: Vec<u8>) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    /// Limits the witness to a range of block heights, it must be set
+    /// before the transaction is signed
+    pub fn with_window(mut self, window: ValidityWindow) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Witness as laid out in a built transaction, with the memo and the
+    /// validity window spliced in after the witness type
+    fn wire_witness(&self) -> Vec<u8> {
+        if self.memo.is_empty() && self.window.is_none() {
+            return self.witness.clone();
+        }
+        let (kind, signatures) = match self.witness.split_first() {
+            Some((kind, signatures)) => (*kind, signatures),
+            None => (WITNESS_TYPE_NONE, &[][..]),
+        };
+        let mut witness = Vec::with_capacity(self.wire_witness_len());
+        let mut flags = 0;
+        if !self.memo.is_empty() {
+            flags |= WITNESS_FLAG_MEMO;
+        }
+        if self.window.is_some() {
+            flags |= WITNESS_FLAG_WINDOW;
+        }
+        witness.push(kind | flags);
+        if !self.memo.is_empty() {
+            witness.extend_from_slice(&(self.memo.len() as u16).to_le_bytes());
+            witness.extend_from_slice(&self.memo);
+        }
+        if let Some(window) = &self.window {
+            witness.extend_from_slice(&window.to_bytes());
         }
+        witness.extend_from_slice(signatures);
+        witness
+    }
+
+    /// Length of `wire_witness` without building it
+    fn wire_witness_len(&self) -> usize {
+        if self.memo.is_empty() && self.window.is_none() {
+            return self.witness.len();
+        }
+        // a memo without a witness still gets a type byte
+        let mut len = self.witness.len().max(1);
+        if !self.memo.is_empty() {
+            len += MEMO_LENGTH_SIZE + self.memo.len();
+        }
+        if self.window.is_some() {
+            len += WINDOW_SIZE;
+        }
+        len
+    }
+
+    /// Key transactions are ordered by in a built transaction, the sha256 of the name
+    pub fn sort_key(&self) -> SubspaceHash {
+        SubspaceHash(hash(self.name.as_bytes()))
+    }
+
+    /// Checks the witness the same way the guest would. A registration
+    /// (`current_owner` is `None`) must carry no witness and a valid owner,
+    /// anything else must be signed by `current_owner`.
+    ///
+    /// The signed message commits to the network, so it has to be given
+    /// alongside the space. A validity window isn't checked, the height is
+    /// only known once the guest runs.
+    pub fn verify(&self, space: &str, network: Network, current_owner: Option<&Owner>)
+        -> Result<(), BuilderError> {
+        let current_owner = match current_owner {
+            Some(owner) => owner,
+            None => {
+                if !self.witness.is_empty() {
+                    return Err(BuilderError(format!("registration of {} must not have a witness", self.name)));
+                }
+                if self.window.is_some() {
+                    return Err(BuilderError(format!("registration of {} must not have a validity window", self.name)));
+                }
+                return owner_key(&self.owner)
+                    .map(|_| ())
+                    .map_err(|e| BuilderError(format!("{}: {}", self.name, e)));
+            }
+        };
+
+        let key = self.sort_key();
+        let header = make_header(FORMAT_VERSION, network as u8, space);
+        let mut value = owner_value(current_owner, &[]);
+        let witness = self.wire_witness();
+        let entry = Entry::new(key, self.owner, &witness);
+        handle_transition(&header, key.as_bytes(), &mut value, &entry, None)
+            .map_err(|e| BuilderError(format!("{}: {}", self.name, e)))
     }
 }
 
+fn make_header(version: u8, network: u8, space: &str) -> [u8; HEADER_SIZE] {
+    let mut raw_header = [0u8; HEADER_SIZE];
+    raw_header[0] = version;
+    raw_header[NETWORK_OFFSET] = network;
+    let space_hash = hash(space.as_bytes());
+    raw_header[SPACE_HASH_OFFSET..].copy_from_slice(&space_hash);
+    raw_header
+}
+
+/// Orders transactions the way `TransactionBuilder::build` lays them out
+pub fn canonical_cmp(a: &Transaction, b: &Transaction) -> Ordering {
+    a.sort_key().cmp(&b.sort_key())
+}
+
 pub trait OwnerPublicKey {
-    fn owner_public_key(&self) -> [u8; 32];
+    fn owner_public_key(&self) -> Owner;
+
+    /// Key witnesses for `owner_public_key` are signed with. The owner only
+    /// keeps the x-coordinate and stands for the point with an even y, a key
+    /// whose point has an odd y signs with its negation instead.
+    fn owner_signing_key(&self) -> SigningKey;
 }
 
 impl OwnerPublicKey for SigningKey {
-    fn owner_public_key(&self) -> [u8; 32] {
+    fn owner_public_key(&self) -> Owner {
+        // compressed SEC1 point without its tag byte
         let ep = self.verifying_key().to_encoded_point(true);
-        let k = ep.as_bytes();
-        k[1..].try_into().unwrap()
+        let mut owner = Owner::default();
+        owner.0.copy_from_slice(&ep.as_bytes()[1..]);
+        owner
+    }
+
+    fn owner_signing_key(&self) -> SigningKey {
+        let ep = self.verifying_key().to_encoded_point(true);
+        if ep.as_bytes()[0] == SEC1_ODD_TAG {
+            SigningKey::from(-*self.as_nonzero_scalar())
+        } else {
+            self.clone()
+        }
     }
 }
 
+const MASTER_KEY_SALT: &[u8] = b"subspacer/master-key/v1";
+
+/// Derives the signing key for `subspace@space` from a master key.
+///
+/// The derivation is HKDF-SHA256 with the master secret as input key material,
+/// `subspacer/master-key/v1` as salt and `subspace@space` as info, expanded to
+/// a 32-byte secret scalar. It must stay stable so keys can be recovered later.
+pub fn derive_subspace_key(master: &SigningKey, subspace: &str, space: &str)
+    -> Result<SigningKey, BuilderError> {
+    let hk = Hkdf::<Sha256>::new(Some(MASTER_KEY_SALT), master.to_bytes().as_slice());
+    let mut secret = [0u8; 32];
+    hk.expand(format!("{}@{}", subspace, space).as_bytes(), &mut secret)
+        .map_err(|_| BuilderError(String::from("could not expand master key")))?;
+    SigningKey::from_slice(&secret)
+        .map_err(|_| BuilderError(format!("derived key for {}@{} is invalid", subspace, space)))
+}
+
 #[derive(Debug)]
 pub struct SigningError;
 
@@ -222,5 +716,137 @@ impl std::error::Error for BuilderError {}
 fn hash(slice: &[u8]) -> [u8;32] {
     let mut hasher = Sha256::new();
     hasher.update(slice);
-    hasher.finalize().try_into().unwrap()
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::Signature;
+    use k256::ecdsa::signature::Verifier;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn owner(seed: u8) -> Owner {
+        key(seed).owner_public_key()
+    }
+
+    #[test]
+    fn failed_merge_leaves_builder_unchanged() {
+        let mut builder = TransactionBuilder::new();
+        builder.add(Transaction::new("bob", owner(1)), None).unwrap();
+        let before = builder.clone();
+
+        let mut other = TransactionBuilder::new();
+        for name in ["alice", "bob", "carol"] {
+            other.add(Transaction::new(name, owner(2)), None).unwrap();
+        }
+        assert!(builder.merge(other).is_err());
+        assert_eq!(builder, before);
+    }
+
+    #[test]
+    fn misspelled_field_is_rejected() {
+        let json = format!(r#"{{"name": "alice", "owenr": "{}"}}"#, hex::encode(owner(1).as_bytes()));
+        let err = serde_json::from_str::<Transaction>(&json).unwrap_err();
+        assert!(err.to_string().contains("unknown field `owenr`"), "{}", err);
+    }
+
+    #[test]
+    fn missing_owner_is_rejected() {
+        let err = serde_json::from_str::<Transaction>(r#"{"name": "alice"}"#).unwrap_err();
+        assert!(err.to_string().contains("missing field `owner`"), "{}", err);
+    }
+
+    #[test]
+    fn zero_owner_is_rejected_by_add() {
+        let mut builder = TransactionBuilder::new();
+        assert!(builder.add(Transaction::new("alice", Owner::default()), None).is_err());
+        assert!(builder.transactions.is_empty());
+    }
+
+    #[test]
+    fn zero_owner_is_rejected_in_json() {
+        let json = format!(r#"{{"name": "alice", "owner": "{}"}}"#, "00".repeat(OWNER_SIZE));
+        let err = serde_json::from_str::<Transaction>(&json).unwrap_err();
+        assert!(err.to_string().contains("must not be all zeros"), "{}", err);
+    }
+
+    #[test]
+    fn transfer_to_the_signing_key_is_rejected() {
+        let mut builder = TransactionBuilder::new();
+        let entry = Transaction::new("alice", owner(1));
+        let err = builder.add(entry, Some(("bitcoin", key(1)))).unwrap_err();
+        assert!(err.to_string().contains("already owned by this key"), "{}", err);
+        assert!(builder.transactions.is_empty());
+    }
+
+    #[test]
+    fn signing_and_verification_share_the_preimage() {
+        let mut builder = TransactionBuilder::with_network(Network::Testnet);
+        let entry = Transaction::new("alice", owner(2)).with_memo(b"memo".to_vec());
+        builder.add(entry, Some(("bitcoin", key(1)))).unwrap();
+        let signed = builder.transactions[0].clone();
+
+        let header = make_header(FORMAT_VERSION, Network::Testnet as u8, "bitcoin");
+        let mut expected = transfer_preimage(&header, &signed.sort_key(), &signed.owner).to_vec();
+        expected.extend_from_slice(&signed.memo);
+        let preimage = builder.signing_preimage("bitcoin", "alice").unwrap();
+        assert_eq!(preimage, expected);
+
+        // the builder signed exactly these bytes
+        let signature = Signature::from_slice(&signed.witness[1..]).unwrap();
+        key(1).owner_signing_key().verifying_key().verify(&preimage, &signature).unwrap();
+        // and the guest rebuilds them to check the witness
+        signed.verify("bitcoin", Network::Testnet, Some(&owner(1))).unwrap();
+    }
+
+    #[test]
+    fn json_keys_are_camel_case() {
+        let mut builder = TransactionBuilder::new();
+        let entry = Transaction::new("alice", owner(2))
+            .with_memo(b"memo".to_vec())
+            .with_window(ValidityWindow { valid_from: 100, valid_until: 200 });
+        builder.add(entry, Some(("bitcoin", key(1)))).unwrap();
+
+        let json = serde_json::to_value(&builder).unwrap();
+        let keys = |value: &serde_json::Value| -> Vec<String> {
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        assert_eq!(keys(&json), ["network", "space", "transactions", "version"]);
+        let tx = &json["transactions"][0];
+        assert_eq!(keys(tx), ["memo", "name", "owner", "window", "witness"]);
+        assert_eq!(keys(&tx["window"]), ["validFrom", "validUntil"]);
+
+        let parsed = TransactionBuilder::from_json(json.to_string().as_bytes()).unwrap();
+        assert_eq!(parsed, builder);
+    }
+
+    #[test]
+    fn witness_is_only_valid_inside_its_window() {
+        let mut builder = TransactionBuilder::new();
+        let window = ValidityWindow { valid_from: 100, valid_until: 200 };
+        builder.add(Transaction::new("alice", owner(2)).with_window(window), Some(("bitcoin", key(1)))).unwrap();
+        let signed = &builder.transactions[0];
+
+        let header = make_header(FORMAT_VERSION, Network::Mainnet as u8, "bitcoin");
+        let witness = signed.wire_witness();
+        let entry = Entry::new(signed.sort_key(), signed.owner, &witness);
+        for (height, valid) in [(99, false), (100, true), (200, true), (201, false)] {
+            let mut value = owner_value(&owner(1), &[]);
+            let result = handle_transition(&header, signed.sort_key().as_bytes(), &mut value, &entry, Some(height));
+            assert_eq!(result.is_ok(), valid, "height {}", height);
+        }
+
+        // dropping the window from the witness must not leave a valid one behind
+        let mut stripped = vec![witness[0] & !WITNESS_FLAG_WINDOW];
+        stripped.extend_from_slice(&witness[1 + WINDOW_SIZE..]);
+        let entry = Entry::new(signed.sort_key(), signed.owner, &stripped);
+        let mut value = owner_value(&owner(1), &[]);
+        assert!(handle_transition(&header, signed.sort_key().as_bytes(), &mut value, &entry, Some(150)).is_err());
+    }
 }
@@ -1,15 +1,32 @@
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use k256::ecdsa::{Signature, VerifyingKey};
 use k256::ecdsa::signature::Verifier;
+use k256::schnorr;
+use bincode::enc::write::{SizeWriter, SliceWriter, Writer};
 use serde::{Deserialize, Serialize};
 use spacedb::{Hash, Sha256Hasher, subtree::{SubTree, ValueOrHash}, VerifyError};
-use crate::{Entry, TransactionReader};
+use crate::{Entry, HEADER_SIZE, Owner, OWNER_SIZE, SIGNED_MESSAGE_SIZE, SUBSPACE_HASH_SIZE, SubspaceHash, TransactionReader};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Commitment {
     pub space: Hash,
     pub initial_root: Hash,
     pub final_root: Hash,
+    /// Network the tx set's transactions were checked against
+    pub network: u8,
+    /// Most entries the guest would accept in the tx set
+    pub max_entries: u32,
+    /// Block height witness validity windows were checked against
+    pub height: u32,
+}
+
+/// Block heights a witness is valid at, both bounds included
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ValidityWindow {
+    pub valid_from: u32,
+    pub valid_until: u32,
 }
 
 #[derive(Debug)]
@@ -21,125 +38,519 @@ pub enum GuestError {
     WitnessRequired,
     KeyExists,
     IncompleteSubTree,
+    WrongNetwork,
+    RootMismatch,
+    UnsupportedOwner,
+    MalformedInput,
+    CommitmentMismatch,
+    UnknownSubspace,
+    UnexpectedLeaf,
+    InvalidOwnerLength,
+    /// Tx set carries more transactions than the host allowed
+    BatchTooLarge { max: u32 },
+    /// Transaction is for one of the `RESERVED_SUBSPACE_HASHES`
+    ReservedSubspace,
+    /// The subtree's root couldn't be computed, before or after applying
+    /// the transactions
+    RootComputation,
+    /// Witness isn't valid at the height the guest was given
+    OutsideValidityWindow,
+    /// Wraps an error with the tx set it happened in, `space_hash` is
+    /// unknown if the tx set failed before its header was read
+    Space {
+        index: usize,
+        space_hash: Option<Hash>,
+        source: Box<GuestError>,
+    },
 }
 
-const PUBLIC_KEY_SIZE : usize = 32;
-const SEC1_COMPRESSED_TAG : u8 = 0x02;
-const SEC1_PUBLIC_KEY_SIZE : usize = PUBLIC_KEY_SIZE + 1;
-const WITNESS_TYPE_SIGNATURE : u8 = 0x00;
+const PROOF_TYPE_SIZE : usize = 1;
+const ROOT_SIZE : usize = 32;
+const PUBLIC_KEY_SIZE : usize = OWNER_SIZE;
+const SIGNATURE_SIZE : usize = 64;
+
+/// Leaf value is a tag byte followed by a 32-byte x-only public key and
+/// the memo of the transaction that set it, if any
+pub const VALUE_TYPE_PUBLIC_KEY : u8 = 0x00;
+
+/// Witness is a single signature by the current owner
+pub const WITNESS_TYPE_SIGNATURE : u8 = 0x00;
+/// Witness is a signature by the current owner followed by a signature
+/// by the new owner proving it holds the new key
+pub const WITNESS_TYPE_ROTATION : u8 = 0x01;
+/// Witness carries no signature, used to attach a memo to a registration
+pub const WITNESS_TYPE_NONE : u8 = 0x02;
+/// Witness is a single BIP340 Schnorr signature by the current owner
+pub const WITNESS_TYPE_SCHNORR : u8 = 0x03;
+/// Set on the witness type when a memo with a 2-byte little-endian length
+/// prefix follows it, the memo is appended to the signed message
+pub const WITNESS_FLAG_MEMO : u8 = 0x80;
+pub(crate) const MEMO_LENGTH_SIZE : usize = 2;
+/// Set on the witness type when a `ValidityWindow` follows the memo, the
+/// first and last valid height as 4-byte little-endian integers
+pub const WITNESS_FLAG_WINDOW : u8 = 0x40;
+pub(crate) const WINDOW_SIZE : usize = 8;
+/// Every flag that may be set on a witness type
+pub const WITNESS_FLAGS : u8 = WITNESS_FLAG_MEMO | WITNESS_FLAG_WINDOW;
+
+/// Witness types the guest accepts, with the names used in output
+pub const WITNESS_TYPES : [(u8, &str); 4] = [
+    (WITNESS_TYPE_SIGNATURE, "signature"),
+    (WITNESS_TYPE_ROTATION, "rotation"),
+    (WITNESS_TYPE_NONE, "none"),
+    (WITNESS_TYPE_SCHNORR, "schnorr"),
+];
+
+/// Subtree was generated with `ProofType::Standard`
+pub const PROOF_TYPE_STANDARD : u8 = 0x00;
+/// Subtree was generated with `ProofType::Extended`, it may carry leaves
+/// beyond the ones being changed
+pub const PROOF_TYPE_EXTENDED : u8 = 0x01;
+
+/// Set on the proof type when the transactions may be in any order, the
+/// guest sorts them itself instead of rejecting them. Duplicates are still
+/// rejected either way.
+pub const TX_SET_FLAG_UNSORTED : u8 = 0x80;
+
+/// Most transactions a single tx set may carry unless the host picks
+/// another limit, bounds what one crafted payload can cost to prove
+pub const DEFAULT_MAX_ENTRIES : u32 = 10_000;
 
 pub type Result<T> = core::result::Result<T, GuestError>;
 
-pub fn run(mut input : Vec<Vec<u8>>) -> Result<Vec<Commitment>>  {
+/// Guest input is the network, the most transactions a tx set may carry,
+/// the block height validity windows are checked against and the tx sets
+/// themselves
+pub fn run(network: u8, max_entries: u32, height: u32, mut input : Vec<Vec<u8>>) -> Result<Vec<Commitment>>  {
     let mut commitments = Vec::with_capacity(input.len());
-    for tx_set in input.drain(..) {
-        commitments.push(handle_tx_set(tx_set)?);
+    for (index, tx_set) in input.drain(..).enumerate() {
+        let mut space_hash = None;
+        let commitment = handle_space(network, max_entries, height, tx_set, &mut space_hash).map_err(|e| {
+            GuestError::Space { index, space_hash, source: Box::new(e) }
+        })?;
+        commitments.push(commitment);
     }
 
     Ok(commitments)
 }
 
-pub fn handle_tx_set(mut input: Vec<u8>) -> Result<Commitment> {
+/// Each tx set is laid out as:
+/// 1-byte proof type || 32-byte expected initial root || bincode encoded subtree || transaction
+///
+/// The proof type may have `TX_SET_FLAG_UNSORTED` set for transactions that
+/// aren't in canonical order. A tx set with more than `max_entries`
+/// transactions is rejected before any of them are checked.
+pub fn handle_tx_set(network: u8, max_entries: u32, height: u32, input: Vec<u8>) -> Result<Commitment> {
+    handle_space(network, max_entries, height, input, &mut None)
+}
+
+/// Re-runs a tx set natively and checks it reproduces the `claimed` commitment.
+/// Nothing here depends on risc0, so anyone holding a space's subtree and
+/// transaction can check what a receipt's journal claims without the prover.
+pub fn verify_tx_set(network: u8, max_entries: u32, height: u32, input: Vec<u8>, claimed: &Commitment)
+    -> Result<()> {
+    if handle_tx_set(network, max_entries, height, input)? != *claimed {
+        return Err(GuestError::CommitmentMismatch);
+    }
+    Ok(())
+}
+
+/// Lays out a tx set the way `handle_tx_set` expects it, `root` is the
+/// committed root the subtree must match
+pub fn encode_tx_set(proof_type: u8, root: &Hash, subtree: &SubTree<Sha256Hasher>, tx: &[u8])
+    -> Result<Vec<u8>> {
+    let mut input = alloc::vec![0u8; encoded_tx_set_len(subtree, tx)?];
+    encode_tx_set_into(&mut SliceWriter::new(&mut input), proof_type, root, subtree, tx)?;
+    Ok(input)
+}
+
+/// Number of bytes `encode_tx_set` produces, without encoding the subtree
+pub fn encoded_tx_set_len(subtree: &SubTree<Sha256Hasher>, tx: &[u8]) -> Result<usize> {
+    let mut size = SizeWriter::default();
+    bincode::encode_into_writer(subtree, &mut size, bincode::config::standard())
+        .map_err(|_| GuestError::MalformedInput)?;
+    Ok(PROOF_TYPE_SIZE + ROOT_SIZE + size.bytes_written + tx.len())
+}
+
+/// Like `encode_tx_set` but writes straight into `writer`, so a large
+/// subtree never has to be encoded into a buffer of its own
+pub fn encode_tx_set_into<W: Writer>(writer: &mut W, proof_type: u8, root: &Hash,
+                                     subtree: &SubTree<Sha256Hasher>, tx: &[u8]) -> Result<()> {
+    writer.write(&[proof_type]).map_err(|_| GuestError::MalformedInput)?;
+    writer.write(root).map_err(|_| GuestError::MalformedInput)?;
+    bincode::encode_into_writer(subtree, &mut *writer, bincode::config::standard())
+        .map_err(|_| GuestError::MalformedInput)?;
+    writer.write(tx).map_err(|_| GuestError::MalformedInput)
+}
+
+/// Like `handle_tx_set` but records the space hash as soon as it's known
+/// so errors can be attributed to it
+fn handle_space(network: u8, max_entries: u32, height: u32, mut input: Vec<u8>, space_hash: &mut Option<Hash>)
+    -> Result<Commitment> {
+    if input.len() < PROOF_TYPE_SIZE + ROOT_SIZE {
+        return Err(GuestError::MalformedInput);
+    }
+    // Both proof types share the subtree encoding, extended proofs just
+    // include extra leaves which the merge-walk below steps over. A standard
+    // proof must only carry leaves for keys being changed, anything else
+    // would be proven without a transaction ever touching it
+    let strict = match input[0] & !TX_SET_FLAG_UNSORTED {
+        PROOF_TYPE_STANDARD => true,
+        PROOF_TYPE_EXTENDED => false,
+        _ => return Err(GuestError::MalformedInput),
+    };
+    let unsorted = input[0] & TX_SET_FLAG_UNSORTED != 0;
+    let input = &mut input[PROOF_TYPE_SIZE..];
+    let expected_root: Hash = input[..ROOT_SIZE].try_into().unwrap();
+
     // Decode subtree
     let (mut subtree, subtree_size): (SubTree<Sha256Hasher>, usize) =
-        bincode::decode_from_slice(input.as_slice(), bincode::config::standard()).unwrap();
-    let input = &mut input.as_mut_slice()[subtree_size..];
+        bincode::decode_from_slice(&input[ROOT_SIZE..], bincode::config::standard())
+            .map_err(|_| GuestError::MalformedInput)?;
+    let input = &mut input[ROOT_SIZE + subtree_size..];
 
-    let initial_root = subtree.root().unwrap();
+    let initial_root = checked_root(subtree.root())?;
+    if initial_root != expected_root {
+        return Err(GuestError::RootMismatch);
+    }
 
-    let reader = TransactionReader(input);
+    let reader = TransactionReader::try_new(input).map_err(|_| GuestError::MalformedInput)?;
     let space = reader.space_hash();
+    *space_hash = Some(space.0);
+    if reader.network() != network {
+        return Err(GuestError::WrongNetwork);
+    }
+
+    let header: &[u8; HEADER_SIZE] = reader.header().try_into()
+        .map_err(|_| GuestError::MalformedInput)?;
+    let mut registrations = Vec::new();
+
+    // Transactions and subtree leaves are both sorted by key, so we can
+    // merge-walk them: a transaction whose key has a leaf is an update,
+    // anything else is a registration. Unsorted tx sets are sorted here first.
+    {
+        let mut leaves = subtree.iter_mut();
+        let mut leaf = leaves.next();
+        let mut previous: Option<SubspaceHash> = None;
+
+        let mut entries = Vec::new();
+        for entry in reader.entries() {
+            if entries.len() >= max_entries as usize {
+                return Err(GuestError::BatchTooLarge { max: max_entries });
+            }
+            entries.push(entry.map_err(|_| GuestError::MalformedInput)?);
+        }
+        if unsorted {
+            entries.sort_unstable_by(|a, b| a.subspace_hash().cmp(b.subspace_hash()));
+        }
 
-    let mut buffer = [0u8; 64];
-    let mut transactions = reader.iter();
+        for tx in entries {
+            if previous.is_some_and(|p| p >= *tx.subspace_hash()) {
+                return Err(GuestError::UnalignedSubTree);
+            }
+            previous = Some(*tx.subspace_hash());
 
-    // All leave values included in the subtree are processed
-    // since the transactions are sorted, we can iterate through the subtree
-    // and consume them as we go
-    for ((key, value), tx) in subtree.iter_mut().zip(transactions.by_ref()) {
-        handle_transition(&mut buffer, key, value, &tx)?;
+            while leaf.as_ref().is_some_and(|(key, _)| **key < tx.subspace_hash().0) {
+                if strict {
+                    return Err(GuestError::UnexpectedLeaf);
+                }
+                leaf = leaves.next();
+            }
+
+            match &mut leaf {
+                Some((key, value)) if **key == tx.subspace_hash().0 => {
+                    handle_transition(header, key, value, &tx, Some(height))?;
+                    leaf = leaves.next();
+                }
+                _ => registrations.push(tx),
+            }
+        }
+
+        if strict && leaf.is_some() {
+            return Err(GuestError::UnexpectedLeaf);
+        }
     }
 
-    // All remaining transactions are registrations
-    for registration in transactions {
+    for registration in registrations {
+        if registration.subspace_hash().is_reserved() {
+            return Err(GuestError::ReservedSubspace);
+        }
+        // Catch owners that aren't valid x-coordinates before they're committed
+        owner_key(registration.owner())?;
+        let witness = parse_witness(registration.witness())?;
+        // A signed change for a name that doesn't exist must not slip in as
+        // a registration without its signature ever being checked
+        if witness.kind != WITNESS_TYPE_NONE {
+            return Err(GuestError::UnknownSubspace);
+        }
+        // nothing is signed that a window could bound
+        if witness.window.is_some() {
+            return Err(GuestError::UnsupportedWitness);
+        }
         subtree.insert(
-            registration.subspace_hash.try_into().unwrap(),
-            ValueOrHash::Hash(registration.owner.try_into().unwrap())
+            registration.subspace_hash().0,
+            ValueOrHash::Value(owner_value(registration.owner(), witness.memo))
         )
-            .map_err(|e| match e {
-                spacedb::Error::Verify(e) => {
-                    match e {
-                        VerifyError::IncompleteProof => GuestError::IncompleteSubTree,
-                        VerifyError::KeyNotFound => GuestError::IncompleteSubTree,
-                        VerifyError::KeyExists => GuestError::KeyExists,
-                    }
-                },
-                _ => {
-                    unreachable!("expected verify error")
-                },
-            })?;
+            .map_err(insert_error)?;
     }
 
     // Calculate updated subtree root
-    let final_root = subtree.root().unwrap();
+    let final_root = checked_root(subtree.root())?;
 
     Ok(Commitment {
-        space: space.try_into().unwrap(),
+        space: space.0,
         initial_root,
         final_root,
+        network,
+        max_entries,
+        height,
     })
 }
 
-fn handle_transition(
-    buffer: &mut [u8; 64],
+/// Root of a subtree, one that can't be computed, e.g. because the subtree
+/// is missing nodes, is an error instead of a panic
+fn checked_root(root: spacedb::Result<Hash>) -> Result<Hash> {
+    root.map_err(|_| GuestError::RootComputation)
+}
+
+/// Guest error for a failed subtree insert. Anything but a verify error
+/// means the subtree itself is broken, it's reported rather than panicking.
+fn insert_error(e: spacedb::Error) -> GuestError {
+    match e {
+        spacedb::Error::Verify(VerifyError::IncompleteProof | VerifyError::KeyNotFound) => GuestError::IncompleteSubTree,
+        spacedb::Error::Verify(VerifyError::KeyExists) => GuestError::KeyExists,
+        _ => GuestError::MalformedInput,
+    }
+}
+
+/// Checks `tx`'s witness against the owner in `value` and sets the new one.
+/// A validity window is only checked if the `height` is known, the host
+/// checks witnesses before proving without one.
+pub(crate) fn handle_transition(
+    header: &[u8; HEADER_SIZE],
     key: &[u8; 32],
     value: &mut Vec<u8>,
     tx: &Entry,
+    height: Option<u32>,
 ) -> Result<()> {
-    if key != tx.subspace_hash {
+    if *key != tx.subspace_hash().0 {
         return Err(GuestError::UnalignedSubTree);
     }
-    if value.len() != PUBLIC_KEY_SIZE {
-        return Err(GuestError::ExpectedPublicKey);
+    if tx.subspace_hash().is_reserved() {
+        return Err(GuestError::ReservedSubspace);
     }
+    // Leaf values are tagged with their kind, only single keys are supported for now
+    let (owner, verifying_key) = match value.split_first() {
+        Some((&VALUE_TYPE_PUBLIC_KEY, owner)) => {
+            let owner = owner.get(..PUBLIC_KEY_SIZE).and_then(|owner| Owner::try_from(owner).ok())
+                .ok_or(GuestError::InvalidOwnerLength)?;
+            let key = owner_key(&owner)?;
+            (owner, key)
+        }
+        Some(_) => return Err(GuestError::UnsupportedOwner),
+        None => return Err(GuestError::ExpectedPublicKey),
+    };
 
-    buffer[0] = SEC1_COMPRESSED_TAG;
-    buffer[1..SEC1_PUBLIC_KEY_SIZE].copy_from_slice(value.as_slice());
-
-    let verifying_key =
-        VerifyingKey::from_sec1_bytes(&buffer[..SEC1_PUBLIC_KEY_SIZE])
-            .map_err(|_| GuestError::ExpectedPublicKey)?;
-
-    buffer[..32].copy_from_slice(key);
-    buffer[32..].copy_from_slice(tx.owner);
+    let preimage = transfer_preimage(header, tx.subspace_hash(), tx.owner());
 
-    if tx.witness.is_empty() {
+    if tx.witness().is_empty() {
         return Err(GuestError::WitnessRequired);
     }
-    if tx.witness[0] != WITNESS_TYPE_SIGNATURE {
-        return Err(GuestError::UnsupportedWitness);
+    let witness = parse_witness(tx.witness())?;
+    if let (Some(window), Some(height)) = (witness.window, height) {
+        if height < window.valid_from || height > window.valid_until {
+            return Err(GuestError::OutsideValidityWindow);
+        }
     }
 
-    let signature = Signature::from_slice(&tx.witness[1..])
-        .map_err(|_| GuestError::InvalidSignature)?;
+    // A memo and a validity window are covered by the signatures too
+    let msg = signed_message(&preimage, witness.window.as_ref(), witness.memo);
 
-    verifying_key.verify(buffer, &signature).map_err(|_| GuestError::InvalidSignature)?;
+    match witness.kind {
+        WITNESS_TYPE_SIGNATURE => {
+            verify_signature(&verifying_key, &msg, witness.signatures)?;
+        }
+        WITNESS_TYPE_ROTATION => {
+            if witness.signatures.len() != 2 * SIGNATURE_SIZE {
+                return Err(GuestError::InvalidSignature);
+            }
+            let (old, new) = witness.signatures.split_at(SIGNATURE_SIZE);
+            verify_signature(&verifying_key, &msg, old)?;
+            verify_signature(&owner_key(tx.owner())?, &msg, new)?;
+        }
+        WITNESS_TYPE_SCHNORR => {
+            verify_schnorr(&owner, &msg, witness.signatures)?;
+        }
+        WITNESS_TYPE_NONE => return Err(GuestError::WitnessRequired),
+        _ => return Err(GuestError::UnsupportedWitness),
+    }
 
     // Set the new owner
-    value.copy_from_slice(tx.owner);
+    *value = owner_value(tx.owner(), witness.memo);
     Ok(())
 }
 
+/// A witness split into its type, memo, validity window and signatures
+pub struct Witness<'a> {
+    /// Witness type with the flags cleared
+    pub kind: u8,
+    pub memo: &'a [u8],
+    pub window: Option<ValidityWindow>,
+    pub signatures: &'a [u8],
+}
+
+/// Splits a witness laid out as:
+/// type || [2-byte memo length || memo, if flagged] || [window, if flagged] || signatures
+///
+/// An empty witness parses as `WITNESS_TYPE_NONE` without a memo.
+pub fn parse_witness(witness: &[u8]) -> Result<Witness<'_>> {
+    let (&kind, mut rest) = match witness.split_first() {
+        Some(split) => split,
+        None => return Ok(Witness { kind: WITNESS_TYPE_NONE, memo: &[], window: None, signatures: &[] }),
+    };
+
+    let mut memo: &[u8] = &[];
+    if kind & WITNESS_FLAG_MEMO != 0 {
+        if rest.len() < MEMO_LENGTH_SIZE {
+            return Err(GuestError::MalformedInput);
+        }
+        let len = u16::from_le_bytes(rest[..MEMO_LENGTH_SIZE].try_into().unwrap()) as usize;
+        rest = &rest[MEMO_LENGTH_SIZE..];
+        if len > rest.len() {
+            return Err(GuestError::MalformedInput);
+        }
+        (memo, rest) = rest.split_at(len);
+    }
+
+    let mut window = None;
+    if kind & WITNESS_FLAG_WINDOW != 0 {
+        if rest.len() < WINDOW_SIZE {
+            return Err(GuestError::MalformedInput);
+        }
+        let (bounds, signatures) = rest.split_at(WINDOW_SIZE);
+        window = Some(ValidityWindow::from_bytes(bounds.try_into().unwrap()));
+        rest = signatures;
+    }
+    Ok(Witness { kind: kind & !WITNESS_FLAGS, memo, window, signatures: rest })
+}
+
+impl ValidityWindow {
+    pub fn to_bytes(&self) -> [u8; WINDOW_SIZE] {
+        let mut bytes = [0u8; WINDOW_SIZE];
+        bytes[..4].copy_from_slice(&self.valid_from.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.valid_until.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; WINDOW_SIZE]) -> Self {
+        ValidityWindow {
+            valid_from: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+            valid_until: u32::from_le_bytes(bytes[4..].try_into().unwrap()),
+        }
+    }
+}
+
+/// What a witness actually signs, the `transfer_preimage` with the memo
+/// appended. A validity window goes in front, where a witness without one
+/// always has the tx set header, so one can't be passed off as the other.
+pub fn signed_message(preimage: &[u8; SIGNED_MESSAGE_SIZE], window: Option<&ValidityWindow>, memo: &[u8])
+    -> Vec<u8> {
+    let mut msg = Vec::with_capacity(WINDOW_SIZE + SIGNED_MESSAGE_SIZE + memo.len());
+    if let Some(window) = window {
+        msg.extend_from_slice(&window.to_bytes());
+    }
+    msg.extend_from_slice(preimage);
+    msg.extend_from_slice(memo);
+    msg
+}
+
+/// Message a witness signs: the tx set header followed by the entry's
+/// subspace hash and new owner. The builder, the guest and anything else
+/// checking witnesses all build it here. A memo is appended to it.
+pub fn transfer_preimage(header: &[u8; HEADER_SIZE], subspace_hash: &SubspaceHash, owner: &Owner)
+    -> [u8; SIGNED_MESSAGE_SIZE] {
+    let mut msg = [0u8; SIGNED_MESSAGE_SIZE];
+    msg[..HEADER_SIZE].copy_from_slice(header);
+    msg[HEADER_SIZE..HEADER_SIZE + SUBSPACE_HASH_SIZE].copy_from_slice(subspace_hash.as_bytes());
+    msg[HEADER_SIZE + SUBSPACE_HASH_SIZE..].copy_from_slice(owner.as_bytes());
+    msg
+}
+
+/// Encodes an x-only public key owner and an optional memo as a tagged leaf value
+pub fn owner_value(owner: &Owner, memo: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(1 + OWNER_SIZE + memo.len());
+    value.push(VALUE_TYPE_PUBLIC_KEY);
+    value.extend_from_slice(owner.as_bytes());
+    value.extend_from_slice(memo);
+    value
+}
+
+fn verify_signature(key: &VerifyingKey, msg: &[u8], signature: &[u8]) -> Result<()> {
+    let signature = Signature::from_slice(signature)
+        .map_err(|_| GuestError::InvalidSignature)?;
+    key.verify(msg, &signature).map_err(|_| GuestError::InvalidSignature)
+}
+
+/// Verifies a BIP340 signature over SHA-256 of `msg`, the x-only owner
+/// is taken as is since BIP340 keys are implicitly even
+fn verify_schnorr(owner: &Owner, msg: &[u8], signature: &[u8]) -> Result<()> {
+    let key = schnorr::VerifyingKey::from_bytes(owner.as_bytes())
+        .map_err(|_| GuestError::ExpectedPublicKey)?;
+    let signature = schnorr::Signature::try_from(signature)
+        .map_err(|_| GuestError::InvalidSignature)?;
+    key.verify(msg, &signature).map_err(|_| GuestError::InvalidSignature)
+}
+
+/// Parses a 32-byte x-only owner as a compressed SEC1 public key
+pub(crate) fn owner_key(owner: &Owner) -> Result<VerifyingKey> {
+    VerifyingKey::from_sec1_bytes(&owner.to_sec1())
+        .map_err(|_| GuestError::ExpectedPublicKey)
+}
+
 impl core::fmt::Display for GuestError {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match *self {
             GuestError::ExpectedPublicKey => write!(f, "Parse error: expected a public key"),
-            GuestError::UnalignedSubTree => write!(f, "Updates to subtree must be sorted"),
+            GuestError::UnalignedSubTree => write!(f, "Transactions must be sorted by subspace hash"),
             GuestError::InvalidSignature => write!(f, "Invalid signature"),
             GuestError::UnsupportedWitness => write!(f, "Unsupported witness"),
             GuestError::WitnessRequired => write!(f, "Changes to an existing name require a witness"),
             GuestError::KeyExists => write!(f, "Cannot register a name that already exists"),
             GuestError::IncompleteSubTree => write!(f, "SubTree is incomplete"),
+            GuestError::WrongNetwork => write!(f, "Transaction is for a different network"),
+            GuestError::RootMismatch => write!(f, "SubTree root does not match the expected initial root"),
+            GuestError::UnsupportedOwner => write!(f, "Unsupported owner value type"),
+            GuestError::MalformedInput => write!(f, "Malformed transaction set"),
+            GuestError::CommitmentMismatch => write!(f, "Tx set does not produce the claimed commitment"),
+            GuestError::UnknownSubspace => write!(f, "Cannot change a name that does not exist"),
+            GuestError::UnexpectedLeaf => write!(f, "Subtree proves a key no transaction changes"),
+            GuestError::InvalidOwnerLength => write!(f, "Owner must be a 32-byte public key"),
+            GuestError::BatchTooLarge { max } => write!(f, "Tx set has more than {} transactions", max),
+            GuestError::ReservedSubspace => write!(f, "Subspace hash is reserved"),
+            GuestError::RootComputation => write!(f, "Could not compute the subtree root"),
+            GuestError::OutsideValidityWindow => write!(f, "Witness is not valid at this block height"),
+            GuestError::Space { index, space_hash, ref source } => {
+                write!(f, "space {}", index)?;
+                if let Some(hash) = space_hash {
+                    write!(f, " (")?;
+                    for byte in hash {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                    write!(f, ")")?;
+                }
+                write!(f, " failed: {}", source)
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incomplete_subtree_root_is_an_error() {
+        let root = checked_root(Err(spacedb::Error::Verify(VerifyError::IncompleteProof)));
+        assert!(matches!(root, Err(GuestError::RootComputation)));
+    }
+}
@@ -5,16 +5,141 @@ extern crate core;
 #[cfg(feature = "std")]
 pub mod builder;
 pub mod guest;
+#[cfg(feature = "std")]
+pub mod name;
+#[cfg(feature = "std")]
+pub mod vectors;
 
 pub struct TransactionReader<'a>(pub &'a [u8]);
 
-pub const HEADER_SIZE: usize = 1 /* version */ + 32 /* space hash */;
+/// Version byte of the transaction format this crate reads and writes
+pub const FORMAT_VERSION: u8 = 0;
+
+pub const VERSION_SIZE: usize = 1;
+pub const NETWORK_SIZE: usize = 1;
+pub const SPACE_HASH_SIZE: usize = 32;
+pub const HEADER_SIZE: usize = VERSION_SIZE + NETWORK_SIZE + SPACE_HASH_SIZE;
+
+/// Witness lengths, like every length in the format, are little-endian u16s
+pub const LENGTH_PREFIX_SIZE: usize = 2;
+pub const SUBSPACE_HASH_SIZE: usize = 32;
+pub const OWNER_SIZE: usize = 32;
+/// Every entry starts with a subspace hash and owner, followed by the witness
+pub const ENTRY_FIXED_SIZE: usize = SUBSPACE_HASH_SIZE + OWNER_SIZE;
+
+/// Compressed SEC1 public key, a parity tag followed by the x-coordinate
+pub const SEC1_OWNER_SIZE: usize = OWNER_SIZE + 1;
+pub const SEC1_EVEN_TAG: u8 = 0x02;
+pub const SEC1_ODD_TAG: u8 = 0x03;
+
+/// Witnesses sign the header followed by the subspace hash and new owner
+pub const SIGNED_MESSAGE_SIZE: usize = HEADER_SIZE + ENTRY_FIXED_SIZE;
+
+pub const NETWORK_OFFSET: usize = VERSION_SIZE;
+pub const SPACE_HASH_OFFSET: usize = VERSION_SIZE + NETWORK_SIZE;
+
+/// Defines a 32-byte value that can't be mixed up with the other kinds,
+/// built from a slice with `try_from` or from an array directly
+macro_rules! byte_newtype {
+    ($(#[$doc:meta])* $name:ident, $size:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(pub [u8; $size]);
+
+        impl $name {
+            pub fn as_bytes(&self) -> &[u8; $size] {
+                &self.0
+            }
+        }
+
+        impl From<[u8; $size]> for $name {
+            fn from(bytes: [u8; $size]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = core::array::TryFromSliceError;
+
+            fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+                Ok($name(bytes.try_into()?))
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+    };
+}
+
+byte_newtype!(
+    /// Hash of a space name, committed to in the header of a transaction
+    SpaceHash, SPACE_HASH_SIZE
+);
+byte_newtype!(
+    /// Hash of a subspace name, the key of its leaf in the space's tree
+    SubspaceHash, SUBSPACE_HASH_SIZE
+);
+byte_newtype!(
+    /// 32-byte x-only public key a subspace is owned by
+    Owner, OWNER_SIZE
+);
+
+/// Subspace hashes no name may use, all zeros and all ones are kept free
+/// as sentinels in case the key space is ever shortened or relies on them
+pub const RESERVED_SUBSPACE_HASHES: [SubspaceHash; 2] = [
+    SubspaceHash([0x00; SUBSPACE_HASH_SIZE]),
+    SubspaceHash([0xff; SUBSPACE_HASH_SIZE]),
+];
+
+impl SubspaceHash {
+    pub fn is_reserved(&self) -> bool {
+        RESERVED_SUBSPACE_HASHES.contains(self)
+    }
+}
+
+impl Owner {
+    /// Compressed SEC1 form of the key this owner stands for. Owners are
+    /// x-only and always mean the point with an even y
+    pub fn to_sec1(&self) -> [u8; SEC1_OWNER_SIZE] {
+        let mut sec1 = [0u8; SEC1_OWNER_SIZE];
+        sec1[0] = SEC1_EVEN_TAG;
+        sec1[1..].copy_from_slice(&self.0);
+        sec1
+    }
+
+    /// Owner of a compressed SEC1 key of either parity, a key with an odd y
+    /// is owned through its negation which shares the x-coordinate
+    pub fn from_sec1(sec1: &[u8]) -> Option<Self> {
+        match sec1.split_first() {
+            Some((&SEC1_EVEN_TAG | &SEC1_ODD_TAG, x)) => Owner::try_from(x).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet = 0x00,
+    Testnet = 0x01,
+}
 
 impl<'a> TransactionReader<'a> {
     pub fn new(data: &'a [u8]) -> Self {
         TransactionReader(data)
     }
 
+    /// Like `new` but checks there's a complete header, so the header
+    /// accessors can't panic
+    pub fn try_new(data: &'a [u8]) -> Result<Self, ReaderError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ReaderError::TruncatedHeader);
+        }
+        Ok(TransactionReader(data))
+    }
+
     pub fn header(&self) -> &'a [u8] {
         &self.0[..HEADER_SIZE]
     }
@@ -23,59 +148,180 @@ impl<'a> TransactionReader<'a> {
         self.0[0]
     }
 
-    pub fn space_hash(&self) -> &'a [u8] {
-        &self.0[1..33] // Skip 1 byte for version
+    pub fn network(&self) -> u8 {
+        self.0[NETWORK_OFFSET]
+    }
+
+    pub fn space_hash(&self) -> SpaceHash {
+        let mut hash = SpaceHash::default();
+        hash.0.copy_from_slice(&self.0[SPACE_HASH_OFFSET..HEADER_SIZE]);
+        hash
     }
 
     pub fn iter(&self) -> BodyIterator<'a> {
         BodyIterator {
-            data: &self.0[HEADER_SIZE..],
+            inner: self.entries(),
         }
     }
 
+    /// Entries registering new names, see `Entry::is_registration`
+    pub fn registrations(&self) -> impl Iterator<Item = Entry<'a>> {
+        self.iter().filter(|entry| entry.is_registration())
+    }
+
+    /// Entries changing existing names, the ones carrying a signature
+    pub fn updates(&self) -> impl Iterator<Item = Entry<'a>> {
+        self.iter().filter(|entry| !entry.is_registration())
+    }
+
+    /// Like `iter` but reports malformed entries instead of silently stopping
+    pub fn entries(&self) -> EntryIterator<'a> {
+        EntryIterator {
+            data: self.0.get(HEADER_SIZE..).unwrap_or(&[]),
+        }
+    }
 }
 
-pub struct BodyIterator<'a> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderError {
+    /// Fewer bytes than a complete header
+    TruncatedHeader,
+    /// Fewer than 2 bytes left for the length prefix
+    TruncatedLength,
+    /// Length prefix is smaller than subspace hash + owner
+    InvalidLength(usize),
+    /// Length prefix exceeds the remaining data
+    TruncatedEntry { expected: usize, remaining: usize },
+}
+
+pub struct EntryIterator<'a> {
     data: &'a [u8],
 }
 
-impl<'a> Iterator for BodyIterator<'a> {
-    type Item = Entry<'a>;
+impl<'a> Iterator for EntryIterator<'a> {
+    type Item = Result<Entry<'a>, ReaderError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if 2 > self.data.len() {
+        if self.data.is_empty() {
             return None; // No more data to read
         }
+        if LENGTH_PREFIX_SIZE > self.data.len() {
+            self.data = &[];
+            return Some(Err(ReaderError::TruncatedLength));
+        }
         // Parse the 2-byte length of the tx
         let len = u16::from_le_bytes(
-            self.data[.. 2].try_into().unwrap()
+            self.data[..LENGTH_PREFIX_SIZE].try_into().unwrap()
         ) as usize;
 
-        self.data = &self.data[2..];
-        if len > self.data.len() || len < 64 {
-            return None;
+        let data = &self.data[LENGTH_PREFIX_SIZE..];
+        if len < ENTRY_FIXED_SIZE {
+            self.data = &[];
+            return Some(Err(ReaderError::InvalidLength(len)));
+        }
+        if len > data.len() {
+            self.data = &[];
+            return Some(Err(ReaderError::TruncatedEntry { expected: len, remaining: data.len() }));
         }
-        self.data = &self.data[..len];
+        let (entry, rest) = data.split_at(len);
+        self.data = rest;
 
         // Extract subspace hash, owner, and witness from the update data
-        let subspace_hash = &self.data[..32];
-        let owner = &self.data[32..64];
-        let witness = &self.data[64..];
+        let (subspace_hash, entry) = entry.split_at(SUBSPACE_HASH_SIZE);
+        let (owner, witness) = entry.split_at(OWNER_SIZE);
+        match (SubspaceHash::try_from(subspace_hash), Owner::try_from(owner)) {
+            (Ok(subspace_hash), Ok(owner)) => Some(Ok(Entry { subspace_hash, owner, witness })),
+            _ => Some(Err(ReaderError::InvalidLength(len))),
+        }
+    }
+}
 
-        assert_eq!(witness.len(), len - 64, "witness length mismatch");
+pub struct BodyIterator<'a> {
+    inner: EntryIterator<'a>,
+}
 
-        self.data = &self.data[len..];
+impl<'a> Iterator for BodyIterator<'a> {
+    type Item = Entry<'a>;
 
-        Some(Entry {
-            subspace_hash,
-            owner,
-            witness,
-        })
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()?.ok()
     }
 }
 
 pub struct Entry<'a> {
-    pub subspace_hash: &'a [u8],
-    pub owner: &'a [u8],
-    pub witness: &'a [u8],
+    subspace_hash: SubspaceHash,
+    owner: Owner,
+    witness: &'a [u8],
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(subspace_hash: SubspaceHash, owner: Owner, witness: &'a [u8]) -> Self {
+        Entry { subspace_hash, owner, witness }
+    }
+
+    pub fn subspace_hash(&self) -> &SubspaceHash {
+        &self.subspace_hash
+    }
+
+    pub fn owner(&self) -> &Owner {
+        &self.owner
+    }
+
+    pub fn witness(&self) -> &'a [u8] {
+        self.witness
+    }
+
+    /// Whether the witness carries no signature, which only a registration
+    /// may do. A witness holding just a memo still counts as one.
+    pub fn is_registration(&self) -> bool {
+        match self.witness.first() {
+            Some(kind) => kind & !guest::WITNESS_FLAGS == guest::WITNESS_TYPE_NONE,
+            None => true,
+        }
+    }
+}
+
+impl TryFrom<u8> for Network {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(Network::Mainnet),
+            0x01 => Ok(Network::Testnet),
+            _ => Err(()),
+        }
+    }
+}
+
+impl core::str::FromStr for Network {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            _ => Err("expected mainnet or testnet"),
+        }
+    }
+}
+
+impl core::fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ReaderError::TruncatedHeader => write!(f, "truncated header"),
+            ReaderError::TruncatedLength => write!(f, "truncated length prefix"),
+            ReaderError::InvalidLength(len) => write!(f, "entry length {} is too short", len),
+            ReaderError::TruncatedEntry { expected, remaining } =>
+                write!(f, "entry needs {} bytes but only {} remain", expected, remaining),
+        }
+    }
+}
+
+impl core::fmt::Display for Network {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Network::Mainnet => write!(f, "mainnet"),
+            Network::Testnet => write!(f, "testnet"),
+        }
+    }
 }
@@ -0,0 +1,201 @@
+// Not part of the guest program
+//
+// Names as users type them, `subspace@space`. Labels are checked and
+// normalized here so every binary hashes the same name the same way.
+
+use core::fmt;
+use unicode_script::{Script, UnicodeScript};
+
+/// Parses `subspace@space` and returns both labels in their canonical form
+pub fn verify_name(subspace: &str) -> Result<(String, String), NameError> {
+    let (subspace, space) = parse_name(subspace).ok_or(NameError::Malformed)?;
+    let space = normalize_label(LabelKind::Space, space.as_str())?;
+    Ok((normalize_label(LabelKind::Subspace, subspace.as_str())?, space))
+}
+
+#[derive(Debug)]
+pub enum NameError {
+    /// Not of the form `subspace@space`
+    Malformed,
+    Label(LabelError),
+}
+
+impl From<LabelError> for NameError {
+    fn from(e: LabelError) -> Self {
+        NameError::Label(e)
+    }
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NameError::Malformed => write!(f, "Invalid subspace name"),
+            NameError::Label(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// Longest label accepted for either component of a name
+pub const MAX_LABEL_LEN: usize = 63;
+
+/// Prefix of the ascii compatible encoding of an internationalized label
+const ACE_PREFIX: &str = "xn--";
+
+#[derive(Debug, Clone, Copy)]
+pub enum LabelKind {
+    Space,
+    Subspace,
+}
+
+#[derive(Debug)]
+pub enum LabelViolation {
+    Empty,
+    TooLong(usize),
+    Uppercase(char),
+    InvalidChar(char),
+    Idna,
+    MixedScript(Script, Script),
+}
+
+#[derive(Debug)]
+pub struct LabelError {
+    pub kind: LabelKind,
+    pub label: String,
+    pub violation: LabelViolation,
+}
+
+/// Spaces and subspaces follow the same rules: 1 to 63 lowercase ascii letters
+fn validate_label(kind: LabelKind, label: &str) -> Result<(), LabelError> {
+    let violation = if label.is_empty() {
+        Some(LabelViolation::Empty)
+    } else if label.len() > MAX_LABEL_LEN {
+        Some(LabelViolation::TooLong(label.len()))
+    } else {
+        label.chars().find(|c| !c.is_ascii_lowercase()).map(|c| {
+            if c.is_ascii_uppercase() {
+                LabelViolation::Uppercase(c)
+            } else {
+                LabelViolation::InvalidChar(c)
+            }
+        })
+    };
+
+    match violation {
+        Some(violation) => Err(LabelError { kind, label: label.to_string(), violation }),
+        None => Ok(()),
+    }
+}
+
+/// Returns the canonical form of a label, which is what gets hashed.
+/// Unicode labels are mapped and normalized as in IDNA (UTS #46) and
+/// encoded as punycode, so `münchen` and `xn--mnchen-3ya` are the same
+/// name. Punycode labels must already be in that canonical form
+pub fn normalize_label(kind: LabelKind, label: &str) -> Result<String, LabelError> {
+    if label.is_ascii() && !label.starts_with(ACE_PREFIX) {
+        validate_label(kind, label)?;
+        return Ok(label.to_string());
+    }
+
+    let error = |violation| LabelError { kind, label: label.to_string(), violation };
+    let ascii = idna::domain_to_ascii_strict(label)
+        .map_err(|_| error(LabelViolation::Idna))?;
+    if ascii.contains('.') {
+        return Err(error(LabelViolation::InvalidChar('.')));
+    }
+    if !ascii.starts_with(ACE_PREFIX) {
+        // e.g. fullwidth letters map to plain ascii
+        validate_label(kind, &ascii)?;
+        return Ok(ascii);
+    }
+    if label.is_ascii() && ascii != label {
+        return Err(error(LabelViolation::Idna));
+    }
+    if ascii.len() > MAX_LABEL_LEN {
+        return Err(error(LabelViolation::TooLong(ascii.len())));
+    }
+
+    let (unicode, _) = idna::domain_to_unicode(&ascii);
+    if let Some(c) = unicode.chars().find(|c| {
+        if c.is_ascii() {
+            !c.is_ascii_lowercase()
+        } else {
+            !c.is_alphabetic() && c.script() != Script::Inherited
+        }
+    }) {
+        return Err(error(LabelViolation::InvalidChar(c)));
+    }
+    if let Some((a, b)) = mixed_scripts(&unicode) {
+        return Err(error(LabelViolation::MixedScript(a, b)));
+    }
+    Ok(ascii)
+}
+
+/// Finds two scripts that shouldn't appear together in a label, the usual
+/// way of spoofing one name with lookalike letters (e.g. a cyrillic `а`
+/// in `аpple`). Han may be mixed with the scripts it is written with
+fn mixed_scripts(label: &str) -> Option<(Script, Script)> {
+    const ALLOWED: [&[Script]; 3] = [
+        &[Script::Han, Script::Hiragana, Script::Katakana],
+        &[Script::Han, Script::Bopomofo],
+        &[Script::Han, Script::Hangul],
+    ];
+
+    let mut scripts = Vec::new();
+    for script in label.chars().map(|c| c.script()) {
+        if script != Script::Common && script != Script::Inherited && !scripts.contains(&script) {
+            scripts.push(script);
+        }
+    }
+    if scripts.len() < 2 || ALLOWED.iter().any(|set| scripts.iter().all(|s| set.contains(s))) {
+        return None;
+    }
+    Some((scripts[0], scripts[1]))
+}
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LabelKind::Space => write!(f, "Invalid space name @{}: ", self.label)?,
+            LabelKind::Subspace => write!(f, "Invalid subspace {}: ", self.label)?,
+        }
+        match self.violation {
+            LabelViolation::Empty => write!(f, "must not be empty"),
+            LabelViolation::TooLong(len) =>
+                write!(f, "{} characters is longer than {}", len, MAX_LABEL_LEN),
+            LabelViolation::Uppercase(c) => write!(f, "uppercase '{}' is not allowed", c),
+            LabelViolation::InvalidChar(c) => write!(f, "'{}' is not a lowercase letter", c),
+            LabelViolation::Idna => write!(f, "not a valid internationalized name"),
+            LabelViolation::MixedScript(a, b) =>
+                write!(f, "mixes {} and {} letters", a.full_name(), b.full_name()),
+        }
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+/// Splits `subspace@space` into its labels as given, without checking them
+pub fn parse_name(subspace: &str) -> Option<(String, String)> {
+    let mut parts = subspace.split('@');
+    if let (Some(label), Some(space)) = (parts.next(), parts.next()) {
+        if parts.next().is_some() {
+            return None;
+        }
+        return Some((label.to_string(), space.to_string()))
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unicode_and_punycode_are_the_same_name() {
+        let unicode = verify_name("münchen@example").unwrap();
+        assert_eq!(unicode, verify_name("xn--mnchen-3ya@example").unwrap());
+        assert_eq!(unicode.0, "xn--mnchen-3ya");
+    }
+}
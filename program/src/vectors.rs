@@ -0,0 +1,207 @@
+// Not part of the guest program
+
+use k256::ecdsa::SigningKey;
+use serde::Serialize;
+use serde_with::serde_as;
+use serde_with::hex::Hex;
+use sha2::{Digest, Sha256};
+use spacedb::{Sha256Hasher, subtree::{SubTree, ValueOrHash}};
+
+use crate::builder::{OwnerPublicKey, SignatureScheme, Transaction, TransactionBuilder};
+use crate::guest::{encode_tx_set, handle_tx_set, owner_value, Commitment, DEFAULT_MAX_ENTRIES, PROOF_TYPE_STANDARD};
+use crate::Network;
+
+const VECTOR_SPACE: &str = "example";
+
+/// A canonical transaction blob and the commitment the guest produces for it.
+/// Other implementations can use these to check their encoding byte for byte.
+#[serde_as]
+#[derive(Serialize, Debug)]
+pub struct TestVector {
+    pub description: String,
+    pub space: String,
+    pub builder: TransactionBuilder,
+
+    #[serde_as(as = "Hex")]
+    pub tx: Vec<u8>,
+
+    pub commitment: VectorCommitment,
+}
+
+#[serde_as]
+#[derive(Serialize, Debug)]
+pub struct VectorCommitment {
+    #[serde_as(as = "Hex")]
+    pub space: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub initial_root: [u8; 32],
+    #[serde_as(as = "Hex")]
+    pub final_root: [u8; 32],
+    pub network: u8,
+    pub max_entries: u32,
+    pub height: u32,
+}
+
+impl From<Commitment> for VectorCommitment {
+    fn from(c: Commitment) -> Self {
+        Self {
+            space: c.space,
+            initial_root: c.initial_root,
+            final_root: c.final_root,
+            network: c.network,
+            max_entries: c.max_entries,
+            height: c.height,
+        }
+    }
+}
+
+/// Derives the `index`-th signing key from `seed`.
+pub fn derive_key(seed: &[u8; 32], index: u32) -> SigningKey {
+    let mut hasher = Sha256::new();
+    hasher.update(seed);
+    hasher.update(index.to_le_bytes());
+    let secret: [u8; 32] = hasher.finalize().into();
+    SigningKey::from_slice(&secret).expect("valid secret key")
+}
+
+/// Generates test vectors for the transaction format from a fixed seed.
+/// ECDSA signatures are RFC6979 deterministic and BIP340 ones are made without
+/// auxiliary randomness, so the output is stable for a given seed.
+pub fn generate(seed: &[u8; 32]) -> Result<Vec<TestVector>, String> {
+    let alice = derive_key(seed, 0);
+    let bob = derive_key(seed, 1);
+    let carol = derive_key(seed, 2);
+    let dave = derive_key(seed, 3);
+
+    let mut vectors = Vec::new();
+
+    // Registrations into an empty subtree
+    let mut builder = TransactionBuilder::new();
+    builder.add(Transaction::new("alice", alice.owner_public_key()), None)
+        .map_err(|e| e.to_string())?;
+    builder.add(Transaction::new("bob", bob.owner_public_key()), None)
+        .map_err(|e| e.to_string())?;
+    vectors.push(make_vector("registrations", builder, SubTree::empty())?);
+
+    // Transfer of an existing name alongside a new registration
+    let mut existing = SubTree::empty();
+    existing.insert(
+        hash("alice"),
+        ValueOrHash::Value(owner_value(&alice.owner_public_key(), &[])),
+    ).map_err(|_| String::from("could not populate subtree"))?;
+
+    let mut builder = TransactionBuilder::new();
+    builder.add(
+        Transaction::new("alice", carol.owner_public_key()),
+        Some((VECTOR_SPACE, alice)),
+    ).map_err(|e| e.to_string())?;
+    builder.add(Transaction::new("carol", carol.owner_public_key()), None)
+        .map_err(|e| e.to_string())?;
+    vectors.push(make_vector("transfer and registration", builder, existing)?);
+
+    // Transfer signed with a BIP340 witness
+    let mut existing = SubTree::empty();
+    existing.insert(
+        hash("bob"),
+        ValueOrHash::Value(owner_value(&bob.owner_public_key(), &[])),
+    ).map_err(|_| String::from("could not populate subtree"))?;
+
+    let mut builder = TransactionBuilder::new();
+    builder.set_scheme(SignatureScheme::Schnorr);
+    builder.add(
+        Transaction::new("bob", carol.owner_public_key()),
+        Some((VECTOR_SPACE, bob)),
+    ).map_err(|e| e.to_string())?;
+    vectors.push(make_vector("schnorr transfer", builder, existing)?);
+
+    // Registration with a memo, which adds a witness with a length prefix
+    let mut builder = TransactionBuilder::new();
+    builder.add(
+        Transaction::new("dave", dave.owner_public_key()).with_memo(b"hello".to_vec()),
+        None,
+    ).map_err(|e| e.to_string())?;
+    vectors.push(make_vector("registration with memo", builder, SubTree::empty())?);
+
+    // A header without entries leaves the root untouched
+    vectors.push(make_vector("empty", TransactionBuilder::new(), SubTree::empty())?);
+
+    Ok(vectors)
+}
+
+/// Transactions the vectors of the zero seed build to, in hex. Lengths are
+/// little-endian, other implementations have to match these byte for byte.
+const PINNED_TXS: &[(&str, &str)] = &[
+    ("registrations", "000050d858e0985ecc7f60418aaf0cc5ab587f42c2570a884095a9e8ccacd0f6545c40002bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e902a031a5f2dc32de897848d5acf177614f90a3663c53bc6fbeca23077e2bd1063400081b637d8fcd2c6da6359e6963113a1170de795e4b725b84d1e0b4cfd9ec58ce947b8de214e834275dcb8a33a26f4bf6314e870a288d6ddd37752627c47c2f2b0"),
+    ("transfer and registration", "000050d858e0985ecc7f60418aaf0cc5ab587f42c2570a884095a9e8ccacd0f6545c81002bd806c97f0e00af1a1fc3328fa763a9269723c8db8fac4f93af71db186d6e90e071d42015c2204bdaedc285ab717a5e3b2eae1e3c882d4fe6ad0e43d5e26622007da7bcd16bf067d4a3b565dbaa57a62eef4150c156ffaefcc128d7daa43808bb74d5b95bf70a09c92b35acb0a631f82216003653bf2b51d93e40560d8f6c3dc040004c26d9074c27d89ede59270c0ac14b71e071b15239519f75474b2f3ba63481f5e071d42015c2204bdaedc285ab717a5e3b2eae1e3c882d4fe6ad0e43d5e26622"),
+    ("schnorr transfer", "000050d858e0985ecc7f60418aaf0cc5ab587f42c2570a884095a9e8ccacd0f6545c810081b637d8fcd2c6da6359e6963113a1170de795e4b725b84d1e0b4cfd9ec58ce9e071d42015c2204bdaedc285ab717a5e3b2eae1e3c882d4fe6ad0e43d5e26622032dbc72a0cac0a4b2a5bc6aad1e9e34519305a24530eef1cc5e027ce9ebbbcb25ff6ba9a5514e934cccea65cb252fa15b0d649c8a0afff3bb83329fe926eb1786"),
+    ("registration with memo", "000050d858e0985ecc7f60418aaf0cc5ab587f42c2570a884095a9e8ccacd0f6545c480061ea0803f8853523b777d414ace3130cd4d3f92de2cd7ff8695c337d79c2eeee570597fa5982c99a3c562c0b1e1fc6acf2a92dd255e548cf5acf4111f144f9d982050068656c6c6f"),
+    ("empty", "000050d858e0985ecc7f60418aaf0cc5ab587f42c2570a884095a9e8ccacd0f6545c"),
+];
+
+/// Generates the vectors of the zero seed and compares their transactions
+/// with `PINNED_TXS`, so a change to the layout or to the byte order of a
+/// length fails here instead of breaking other implementations. Returns the
+/// number of vectors checked.
+pub fn check_pinned() -> Result<usize, String> {
+    let vectors = generate(&[0u8; 32])?;
+    if vectors.len() != PINNED_TXS.len() {
+        return Err(format!("{} vectors but {} are pinned", vectors.len(), PINNED_TXS.len()));
+    }
+    for (vector, (description, pinned)) in vectors.iter().zip(PINNED_TXS) {
+        if vector.description != *description {
+            return Err(format!("expected vector \"{}\", got \"{}\"", description, vector.description));
+        }
+        let pinned = hex::decode(pinned).map_err(|e| e.to_string())?;
+        if vector.tx != pinned {
+            let offset = vector.tx.iter().zip(&pinned).position(|(a, b)| a != b)
+                .unwrap_or(vector.tx.len().min(pinned.len()));
+            return Err(format!("{}: built {} bytes, pinned {}, first difference at byte {}",
+                               description, vector.tx.len(), pinned.len(), offset));
+        }
+    }
+    Ok(vectors.len())
+}
+
+fn make_vector(
+    description: &str,
+    builder: TransactionBuilder,
+    subtree: SubTree<Sha256Hasher>,
+) -> Result<TestVector, String> {
+    // Round trip through JSON so the vector also covers the builder encoding
+    let json = builder.to_json().map_err(|e| e.to_string())?;
+    let decoded = TransactionBuilder::from_json(json.as_bytes()).map_err(|e| e.to_string())?;
+    let estimated_size = decoded.estimated_size();
+    let tx = decoded.build(VECTOR_SPACE).map_err(|e| e.to_string())?;
+    if tx.len() != estimated_size {
+        return Err(format!("{}: built {} bytes, estimated {}", description, tx.len(), estimated_size));
+    }
+
+    let root = subtree.root()
+        .map_err(|_| String::from("could not compute subtree root"))?;
+    let input = encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, &tx)
+        .map_err(|e| e.to_string())?;
+
+    let commitment = handle_tx_set(Network::Mainnet as u8, DEFAULT_MAX_ENTRIES, 0, input).map_err(|e| e.to_string())?;
+
+    Ok(TestVector {
+        description: String::from(description),
+        space: String::from(VECTOR_SPACE),
+        builder,
+        tx,
+        commitment: commitment.into(),
+    })
+}
+
+fn hash(name: &str) -> [u8; 32] {
+    Sha256::digest(name.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_match_pinned_bytes() {
+        assert_eq!(check_pinned(), Ok(PINNED_TXS.len()));
+    }
+}
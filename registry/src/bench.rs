@@ -0,0 +1,72 @@
+// Executes synthetic batches in the guest without proving, to see how its
+// cycle count scales with the number of transactions
+
+use std::time::Instant;
+use k256::ecdsa::SigningKey;
+use methods::SUBSPACER_ELF;
+use risc0_zkvm::{default_executor, ExecutorEnv};
+use spacedb::Sha256Hasher;
+use spacedb::subtree::{SubTree, ValueOrHash};
+use program::builder::{OwnerPublicKey, Transaction, TransactionBuilder};
+use program::guest::{encode_tx_set, owner_value, PROOF_TYPE_STANDARD};
+use program::{vectors, Network};
+use crate::error::{RegistryError, Result};
+
+const BENCH_SPACE: &str = "bench";
+
+/// Prints cycles per entry for a batch of each size, of registrations or,
+/// with `updates`, of transfers between keys derived from `seed`
+pub fn bench(sizes: &[u32], updates: bool, seed: &[u8; 32]) -> Result<()> {
+    println!("{:<8} {:<14} {:>12} {:>9} {:>12} {:>10}",
+             "Size", "Kind", "Cycles", "Segments", "Cycles/entry", "Time");
+    for &size in sizes {
+        let input = synthetic_tx_set(size, updates, seed)?;
+
+        let mut env = ExecutorEnv::builder();
+        env.write(&(Network::Mainnet as u8))
+            .and_then(|env| env.write(&size))
+            .and_then(|env| env.write(&0u32))
+            .and_then(|env| env.write(&vec![input]))
+            .map_err(|e| RegistryError::Proving(format!("could not write guest input: {}", e)))?;
+        let env = env.build()
+            .map_err(|e| RegistryError::Proving(format!("could not build executor env: {}", e)))?;
+
+        let start = Instant::now();
+        let session = default_executor().execute(env, SUBSPACER_ELF)
+            .map_err(|e| RegistryError::Proving(format!("could not execute elf: {}", e)))?;
+        let elapsed = start.elapsed();
+
+        let cycles : u64 = session.segments.iter().map(|s| s.cycles as u64).sum();
+        println!("{:<8} {:<14} {:>12} {:>9} {:>12} {:>10.2?}",
+                 size, if updates { "updates" } else { "registrations" }, cycles,
+                 session.segments.len(), cycles / size.max(1) as u64, elapsed);
+    }
+    Ok(())
+}
+
+/// A tx set of `size` transactions into an otherwise empty space. Updates
+/// start from a subtree holding the current owners, each name moves from
+/// key `i` to key `size + i`.
+fn synthetic_tx_set(size: u32, updates: bool, seed: &[u8; 32]) -> Result<Vec<u8>> {
+    let mut builder = TransactionBuilder::with_network(Network::Mainnet);
+    let mut subtree = SubTree::<Sha256Hasher>::empty();
+    for i in 0..size {
+        let name = format!("bench-{}", i);
+        let current : SigningKey = vectors::derive_key(seed, i);
+        let entry = if updates {
+            let new_owner = vectors::derive_key(seed, size + i).owner_public_key();
+            let entry = Transaction::new(&name, new_owner);
+            subtree.insert(entry.sort_key().0, ValueOrHash::Value(owner_value(&current.owner_public_key(), &[])))?;
+            builder.add(entry, Some((BENCH_SPACE, current)))
+        } else {
+            builder.add(Transaction::new(&name, current.owner_public_key()), None)
+        };
+        entry.map_err(|e| RegistryError::Validation(format!("could not stage {}: {}", name, e)))?;
+    }
+
+    let raw = builder.build(BENCH_SPACE)
+        .map_err(|e| RegistryError::Staging(format!("could not build tx set: {}", e)))?;
+    let root = subtree.root()?;
+    encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, &raw)
+        .map_err(|e| RegistryError::Proving(format!("could not encode subtree: {}", e)))
+}
@@ -0,0 +1,70 @@
+// Errors the registry reports, grouped by the stage that failed
+
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum RegistryError {
+    /// Staged changes or the local state next to them couldn't be read,
+    /// merged or written
+    Staging(String),
+    /// The prover couldn't be set up, failed or produced an unusable receipt
+    Proving(String),
+    /// A space database failed
+    Db(spacedb::Error),
+    Io(io::Error),
+    /// Input was read fine but is rejected, e.g. a witness that doesn't
+    /// verify or a root that doesn't match
+    Validation(String),
+}
+
+pub type Result<T, E = RegistryError> = std::result::Result<T, E>;
+
+impl RegistryError {
+    /// Name of the variant, printed as the `kind` of JSON errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RegistryError::Staging(_) => "Staging",
+            RegistryError::Proving(_) => "Proving",
+            RegistryError::Db(_) => "Db",
+            RegistryError::Io(_) => "Io",
+            RegistryError::Validation(_) => "Validation",
+        }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::Staging(msg) => write!(f, "{}", msg),
+            RegistryError::Proving(msg) => write!(f, "{}", msg),
+            RegistryError::Db(e) => write!(f, "database error: {}", e),
+            RegistryError::Io(e) => write!(f, "{}", e),
+            RegistryError::Validation(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegistryError::Db(e) => Some(e),
+            RegistryError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RegistryError {
+    fn from(e: io::Error) -> Self {
+        RegistryError::Io(e)
+    }
+}
+
+impl From<spacedb::Error> for RegistryError {
+    fn from(e: spacedb::Error) -> Self {
+        match e {
+            spacedb::Error::IO(e) => RegistryError::Io(e),
+            e => RegistryError::Db(e),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// Local audit trail of staged and committed batches, kept as one JSON
+// record per event under .history/ in the state directory, and the roots
+// spaces were committed at as recorded in committed.log
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+use sha2::{Digest, Sha256};
+use program::builder::TransactionBuilder;
+use crate::error::{RegistryError, Result};
+use crate::{DirArgs, COMMIT_LOG_FILE};
+
+const HISTORY_DIR: &str = ".history";
+
+/// Records kept by default, older ones are removed as new ones are written
+pub const DEFAULT_MAX_HISTORY: usize = 1000;
+
+/// What a recorded batch led to
+pub enum Effect<'a> {
+    /// Everything staged after the batch was added
+    Staged(&'a HashMap<String, TransactionBuilder>),
+    /// Root each space was committed at, hex encoded
+    Committed(&'a HashMap<String, String>),
+}
+
+/// Writes a record of the `batch` and what it led to and removes the oldest
+/// records beyond `max`. With a `max` of 0 nothing is recorded. Callers hold
+/// the staging lock, so the sequence numbers never repeat.
+pub fn record(dirs: &DirArgs, kind: &str, batch: &BTreeMap<String, TransactionBuilder>, effect: Effect,
+              max: usize) -> Result<()> {
+    if max == 0 {
+        return Ok(());
+    }
+    let dir = dirs.state_dir()?.join(HISTORY_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let hash = builders_hash(batch)?;
+    let mut record = serde_json::json!({
+        "kind": kind,
+        "time": time,
+        "hash": hash,
+        "builders": batch,
+    });
+    match effect {
+        Effect::Staged(staged) => {
+            record["staged"] = serde_json::Value::from(builders_hash(&staged.iter().collect::<BTreeMap<_, _>>())?);
+        }
+        Effect::Committed(roots) => {
+            record["roots"] = serde_json::json!(roots.iter().collect::<BTreeMap<_, _>>());
+        }
+    }
+    let str = serde_json::to_string_pretty(&record).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize history record"))
+    })?;
+
+    // names sort in the order records were written, even within a second
+    let files = record_files(dirs)?;
+    let sequence = files.last()
+        .and_then(|name| name.split('-').next()?.parse::<u64>().ok())
+        .map_or(0, |last| last + 1);
+    fs::write(dir.join(format!("{:012}-{}-{}.json", sequence, kind, &hash[..12])), str)?;
+
+    let files = record_files(dirs)?;
+    for old in files.iter().take(files.len().saturating_sub(max)) {
+        fs::remove_file(dir.join(old))?;
+    }
+    Ok(())
+}
+
+/// Hex sha256 of builders as JSON, spaces in order so the same builders
+/// always hash the same
+fn builders_hash<K: serde::Serialize, V: serde::Serialize>(builders: &BTreeMap<K, V>) -> Result<String> {
+    let serialized = serde_json::to_vec(builders).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize builders"))
+    })?;
+    Ok(hex::encode(Sha256::digest(&serialized)))
+}
+
+/// Records oldest first, each holds the `kind` (`add`, `commit` or `migrate`), unix
+/// `time`, the `builders` of the batch and their `hash` (hex sha256 of the
+/// builders as JSON), then either the hash of everything `staged` afterwards
+/// or the `roots` the spaces were committed at
+fn load(dirs: &DirArgs) -> Result<Vec<serde_json::Value>> {
+    let dir = dirs.state_dir()?.join(HISTORY_DIR);
+    let mut records = Vec::new();
+    for name in record_files(dirs)? {
+        let raw = fs::read(dir.join(&name))?;
+        let record : serde_json::Value = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
+            RegistryError::Staging(format!("could not parse {}/{}", HISTORY_DIR, name))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Prints the last `limit` records, newest first
+pub fn log(dirs: &DirArgs, limit: Option<usize>) -> Result<()> {
+    let records = load(dirs)?;
+    if records.is_empty() {
+        println!("No history (records are written by \"registry add\", \"registry commit\" and \"registry migrate\")");
+        return Ok(());
+    }
+    println!("{:<12} {:<7} {:<12} Spaces", "Time", "Kind", "Hash");
+    for record in records.iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        let mut spaces : Vec<String> = record["builders"].as_object().into_iter().flatten()
+            .map(|(space, builder)| {
+                let count = builder["transactions"].as_array().map_or(0, |txs| txs.len());
+                format!("@{} ({})", space, count)
+            })
+            .collect();
+        spaces.sort();
+        let hash = record["hash"].as_str().unwrap_or_default();
+        println!("{:<12} {:<7} {:<12} {}", record["time"].as_u64().unwrap_or_default(), record["kind"].as_str().unwrap_or_default(),
+                 &hash[..hash.len().min(12)], spaces.join(", "));
+    }
+    Ok(())
+}
+
+/// Prints the roots `space` was committed at, oldest first, from the lines
+/// of committed.log that carry roots. A commit that didn't start from the
+/// root the previous one ended at is flagged, the database was changed in
+/// between or commits are missing from the log.
+pub fn roots(dirs: &DirArgs, space: &str) -> Result<()> {
+    let path = dirs.state_dir()?.join(COMMIT_LOG_FILE);
+    let log = if path.exists() { fs::read_to_string(path)? } else { String::new() };
+
+    let mut commits = Vec::new();
+    let mut unrecorded = 0;
+    for line in log.lines() {
+        let fields : Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [hash, time, s, initial_root, final_root] if *s == space => {
+                let time : u64 = time.parse().map_err(|_e| {
+                    RegistryError::Staging(format!("could not parse {}", COMMIT_LOG_FILE))
+                })?;
+                commits.push((time, *hash, *initial_root, *final_root));
+            }
+            // written before roots were recorded
+            [_, _, s] if *s == space => unrecorded += 1,
+            [_, _, _] | [_, _, _, _, _] => {}
+            _ => return Err(RegistryError::Staging(format!("could not parse {}", COMMIT_LOG_FILE))),
+        }
+    }
+    if commits.is_empty() {
+        println!("No roots recorded for @{}", space);
+        return Ok(());
+    }
+
+    println!("{:<12} {:<12} Root", "Time", "Tx set");
+    let mut breaks = 0;
+    let mut previous : Option<&str> = None;
+    for (time, hash, initial_root, final_root) in &commits {
+        if previous.is_some_and(|root| root != *initial_root) {
+            breaks += 1;
+            println!("{:<12} {:<12} {} (does not follow, started from this root)", "", "", initial_root);
+        }
+        println!("{:<12} {:<12} {}", time, &hash[..hash.len().min(12)], final_root);
+        previous = Some(final_root);
+    }
+    println!("{} commits, {} breaks in continuity", commits.len(), breaks);
+    if unrecorded > 0 {
+        println!("{} earlier commits of @{} were logged without roots", unrecorded, space);
+    }
+    Ok(())
+}
+
+fn record_files(dirs: &DirArgs) -> Result<Vec<String>> {
+    let dir = dirs.state_dir()?.join(HISTORY_DIR);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".json") {
+            files.push(name);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -1,24 +1,53 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{fs, io};
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
 use atty::Stream;
-use clap::Parser;
-use spacedb::Error;
-// These constants represent the RISC-V ELF and the image ID generated by risc0-build.
-// The ELF is used for proving and the ID is used for verification.
-use methods::{
-    SUBSPACER_ELF, SUBSPACER_ID
-};
-use risc0_zkvm::{default_prover, ExecutorEnv};
-use spacedb::{Hash};
+use clap::{Parser, Subcommand};
+use fs2::FileExt;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use spacedb::{Hash, Sha256Hasher};
+use spacedb::subtree::SubTree;
 use spacedb::db::Database;
 use spacedb::tx::ProofType;
-use program::builder::TransactionBuilder;
-use program::guest::Commitment;
-use program::TransactionReader;
+use program::builder::{Transaction, TransactionBuilder};
+use program::name::verify_name;
+use program::guest::{
+    owner_value, parse_witness, VALUE_TYPE_PUBLIC_KEY, WITNESS_FLAG_MEMO, WITNESS_FLAG_WINDOW, WITNESS_TYPE_NONE,
+    WITNESS_TYPE_ROTATION, WITNESS_TYPE_SCHNORR, WITNESS_TYPE_SIGNATURE, WITNESS_TYPES,
+};
+use program::{FORMAT_VERSION, HEADER_SIZE, Network, Owner, OWNER_SIZE, TransactionReader};
+use crate::error::{RegistryError, Result};
+use crate::store::{LocalStore, RegistryStore};
+
+#[cfg(feature = "zkvm")]
+mod bench;
+mod error;
+mod history;
+#[cfg(feature = "zkvm")]
+mod prove;
+#[cfg(feature = "server")]
+mod server;
+mod store;
 
 const STAGING_FILE: &str = "uncommitted.json";
+const LOCK_FILE: &str = "uncommitted.lock";
+const NAMES_FILE: &str = "names.json";
+const COMMIT_LOG_FILE: &str = "committed.log";
+
+/// Exit code for `status`, `add` and `commit` when there was nothing to do,
+/// so scripts can tell it apart from success (0) and failure (1)
+const EXIT_NOTHING_TO_DO: u8 = 2;
+
+/// Whether a command did any work
+enum Outcome {
+    Done,
+    NothingToDo,
+}
 
 /// The CLI for the registry
 ///
@@ -32,6 +61,16 @@ const STAGING_FILE: &str = "uncommitted.json";
 
 #[derive(Parser)]
 #[command(bin_name = "registry")]
+pub struct Options {
+    /// Print errors as JSON to stderr
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Cli,
+}
+
+#[derive(Subcommand)]
 pub enum Cli {
     #[command(name = "status")]
     Status(StatusArgs),
@@ -40,13 +79,112 @@ pub enum Cli {
     #[command(name = "add")]
     Add(AddArgs),
 
+    /// Check a builder's witnesses against the committed owners before accepting it
+    #[command(name = "check")]
+    Check(CheckArgs),
+
     /// Prove and commit changes
+    #[cfg(feature = "zkvm")]
     #[command(name = "commit")]
-    Commit(CommitArgs),
+    Commit(prove::CommitArgs),
+
+    /// Prove staged changes without committing them, see apply
+    #[cfg(feature = "zkvm")]
+    #[command(name = "prove")]
+    Prove(prove::ProveArgs),
+
+    /// Commit changes proven by prove after verifying the receipt
+    #[cfg(feature = "zkvm")]
+    #[command(name = "apply")]
+    Apply(prove::ApplyArgs),
 
     /// Issue a certificate for a subspace
     #[command(name = "issue")]
     Issue(IssueArgs),
+
+    /// Verify a receipt written by commit
+    #[cfg(feature = "zkvm")]
+    #[command(name = "verify")]
+    Verify(prove::VerifyArgs),
+
+    /// Export the committed owners of a space as JSON
+    #[command(name = "export")]
+    Export(ExportArgs),
+
+    /// Seed a space's database from exported JSON
+    #[command(name = "import")]
+    Import(ImportArgs),
+
+    /// Move every owner of a space to a new space, each owner signs again
+    #[command(name = "migrate")]
+    Migrate(MigrateArgs),
+
+    /// Prove a single subspace's owner at the committed root
+    #[command(name = "prove-inclusion")]
+    ProveInclusion(ProveInclusionArgs),
+
+    /// Check an inclusion proof against a root
+    #[command(name = "verify-inclusion")]
+    VerifyInclusion(VerifyInclusionArgs),
+
+    /// Print the transaction format version and witness types this binary supports
+    #[command(name = "format-version")]
+    FormatVersion,
+
+    /// Decode a raw transaction blob
+    #[command(name = "decode")]
+    Decode(DecodeArgs),
+
+    /// List staged and committed batches recorded in .history
+    #[command(name = "log")]
+    Log(LogArgs),
+
+    /// List the roots a space has been committed at
+    #[command(name = "history")]
+    History(HistoryArgs),
+
+    /// Execute synthetic batches without proving and report cycles per entry
+    #[cfg(feature = "zkvm")]
+    #[command(name = "bench")]
+    Bench(BenchArgs),
+
+    /// Accept transactions over HTTP
+    #[cfg(feature = "server")]
+    #[command(name = "serve")]
+    Serve(ServeArgs),
+}
+
+/// Where the registry keeps its files, each directory defaults to `-C`
+#[derive(clap::Args, Clone)]
+pub struct DirArgs {
+    #[arg(short = 'C')]
+    c: Option<PathBuf>,
+
+    /// Directory holding the space databases
+    #[arg(long)]
+    db_dir: Option<PathBuf>,
+
+    /// Directory holding staged changes, receipts and commit state
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+}
+
+impl DirArgs {
+    pub(crate) fn db_dir(&self) -> Result<PathBuf, io::Error> {
+        get_working_dir(self.db_dir.as_ref().or(self.c.as_ref()))
+    }
+
+    pub(crate) fn state_dir(&self) -> Result<PathBuf, io::Error> {
+        get_working_dir(self.state_dir.as_ref().or(self.c.as_ref()))
+    }
+
+    /// Creates both directories if they don't exist and checks they can be
+    /// written, commands that write call it first so a bad `-C` fails
+    /// before any work is done rather than on the first write
+    pub(crate) fn prepare(&self) -> Result<(), io::Error> {
+        ensure_writable(&self.db_dir()?)?;
+        ensure_writable(&self.state_dir()?)
+    }
 }
 
 #[derive(clap::Args)]
@@ -55,8 +193,8 @@ pub struct StatusArgs {
     // Show status for an individual space
     pub(crate) space: Option<String>,
 
-    #[arg(short = 'C')]
-    c: Option<String>,
+    #[command(flatten)]
+    dirs: DirArgs,
 }
 
 #[derive(clap::Args)]
@@ -64,18 +202,43 @@ pub struct StatusArgs {
 pub struct AddArgs {
     pub(crate) files: Vec<String>,
 
-    #[arg(short = 'C')]
-    c: Option<String>,
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Network the transactions are expected to target
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Don't record names in the local names index
+    #[arg(long)]
+    no_names: bool,
+
+    /// Skip entries already staged as given and replace staged entries
+    /// that changed, instead of rejecting names that are staged already
+    #[arg(long)]
+    skip_unchanged: bool,
+
+    /// Records to keep in .history, 0 to keep none
+    #[arg(long, default_value_t = history::DEFAULT_MAX_HISTORY)]
+    max_history: usize,
 }
 
 #[derive(clap::Args)]
 #[command(author, version, about, long_about = None)]
-pub struct CommitArgs {
-    #[arg(short = 'C')]
-    c: Option<String>,
+pub struct CheckArgs {
+    /// Builder JSON files, read from stdin if none are given
+    pub(crate) files: Vec<String>,
 
-    #[arg(long, short)]
-    dry_run: bool,
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Network the transactions are expected to target
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Verify the witnesses of each space on all cores
+    #[arg(long)]
+    parallel_verify: bool,
 }
 
 #[derive(clap::Args)]
@@ -85,65 +248,219 @@ pub struct IssueArgs {
     space: String,
 }
 
-fn load_builders(working_dir: &Option<String>) -> Result<HashMap<String, TransactionBuilder>, Error> {
-    let input = get_working_dir(working_dir)?.join(STAGING_FILE);
-    if !std::path::Path::new(input.to_str().unwrap()).exists() {
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ExportArgs {
+    pub(crate) space: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ImportArgs {
+    pub(crate) space: String,
+
+    /// Exported JSON, read from stdin if omitted
+    pub(crate) file: Option<String>,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Replace an existing non-empty database
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct MigrateArgs {
+    pub(crate) old_space: String,
+
+    pub(crate) new_space: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Network the new space's transactions target
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Transfers written by a previous run, signed by their owners
+    #[arg(long)]
+    signed: Option<String>,
+
+    /// Records to keep in .history, 0 to keep none
+    #[arg(long, default_value_t = history::DEFAULT_MAX_HISTORY)]
+    max_history: usize,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ProveInclusionArgs {
+    /// Subspace to prove, as name@space
+    pub(crate) subspace: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Write the proof to a file instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct VerifyInclusionArgs {
+    /// Proof written by prove-inclusion
+    pub(crate) file: String,
+
+    /// Hex encoded root the proof must match
+    #[arg(long)]
+    root: String,
+
+    /// Hex encoded owner the subspace is expected to have
+    #[arg(long)]
+    owner: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct DecodeArgs {
+    pub(crate) file: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct HistoryArgs {
+    pub(crate) space: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct LogArgs {
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Only show the most recent records
+    #[arg(long, short = 'n')]
+    limit: Option<usize>,
+}
+
+#[cfg(feature = "zkvm")]
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct BenchArgs {
+    /// Batch sizes to execute, comma separated
+    #[arg(long, value_delimiter = ',', default_values_t = [10, 100, 1000])]
+    sizes: Vec<u32>,
+
+    /// Transfer existing names instead of registering new ones
+    #[arg(long)]
+    updates: bool,
+
+    /// 32-byte hex seed the keys are derived from, zeros if omitted
+    #[arg(long)]
+    seed: Option<String>,
+}
+
+#[cfg(feature = "server")]
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ServeArgs {
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    /// Network the transactions are expected to target
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Records to keep in .history, 0 to keep none
+    #[arg(long, default_value_t = history::DEFAULT_MAX_HISTORY)]
+    max_history: usize,
+}
+
+fn load_builders(dirs: &DirArgs) -> Result<HashMap<String, TransactionBuilder>> {
+    let input = dirs.state_dir()?.join(STAGING_FILE);
+    if !input.exists() {
         return Ok(HashMap::new());
     }
     let raw = fs::read(input)?;
     let result = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
-        io::Error::new(io::ErrorKind::InvalidData, "could not parse uncommitted.json")
+        RegistryError::Staging(String::from("could not parse uncommitted.json"))
     })?;
     Ok(result)
 }
 
-fn save_builders(builders: &HashMap<String, TransactionBuilder>, working_dir: &Option<String>)
-    -> Result<(), Error> {
+/// Takes an exclusive advisory lock guarding the staging file, the lock is
+/// released when the returned file is dropped
+fn lock_staging(dirs: &DirArgs) -> Result<fs::File> {
+    let path = dirs.state_dir()?.join(LOCK_FILE);
+    let file = fs::OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+fn save_builders(builders: &HashMap<String, TransactionBuilder>, dirs: &DirArgs)
+    -> Result<()> {
     let str = serde_json::to_string_pretty(builders).map_err(|_e| {
-        io::Error::new(io::ErrorKind::InvalidData, "unable to serialize builders")
+        RegistryError::Staging(String::from("unable to serialize builders"))
     })?;
-    let input = get_working_dir(working_dir)?.join(STAGING_FILE);
+    let input = dirs.state_dir()?.join(STAGING_FILE);
     fs::write(input, str)?;
     Ok(())
 }
 
-fn status(args : StatusArgs) -> Result<(), Error> {
-    let builders = load_builders(&args.c)?;
+fn status(args : StatusArgs) -> Result<Outcome> {
+    let builders = load_builders(&args.dirs)?;
     if args.space.is_some() {
         let space = args.space.unwrap();
         println!("On Space: {}", space.as_str());
 
         if !builders.contains_key(space.as_str()) {
             println!("No changes to prove and commit (use \"registry add\" to add changes)");
-            return Ok(());
+            return Ok(Outcome::NothingToDo);
         }
-        let (r, u) = builder_stats(builders.get(space.as_str()).unwrap());
+        let builder = builders.get(space.as_str()).unwrap();
+        let (r, u) = builder_stats(builder);
         println!("Changes to prove and commit:");
-        println!("Registrations: {}, Updates: {}", r, u);
+        println!("Registrations: {}, Updates: {}, Size: {} bytes", r, u, builder.estimated_size());
         println!("  (use \"registry commit\" to prove and commit changes)");
-        return Ok(());
+        return Ok(Outcome::Done);
     }
 
     let num_spaces = builders.len();
     if num_spaces == 0 {
         println!("No changes to prove and commit (use \"registry add\" to add changes)");
-        return Ok(());
+        return Ok(Outcome::NothingToDo);
     }
     let mut registrations = 0;
     let mut updates = 0;
+    let mut size = 0;
 
     for (_, builder) in builders {
         let (r, u) = builder_stats(&builder);
         registrations += r;
         updates += u;
+        size += builder.estimated_size();
     }
 
     println!("Changes to prove and commit:");
-    println!("Total spaces: {}, Total Registrations: {}, Total Updates: {}",
-             num_spaces, registrations, updates);
+    println!("Total spaces: {}, Total Registrations: {}, Total Updates: {}, Total Size: {} bytes",
+             num_spaces, registrations, updates, size);
     println!("  (use \"registry commit\" to prove and commit changes)");
 
-    Ok(())
+    Ok(Outcome::Done)
 }
 
 fn builder_stats(builder: &TransactionBuilder) -> (usize, usize) {
@@ -159,219 +476,846 @@ fn builder_stats(builder: &TransactionBuilder) -> (usize, usize) {
     (registrations, updates)
 }
 
-fn add(args: AddArgs) -> Result<(), Error> {
-    let mut builders = load_builders(&args.c)?;
+fn add(args: AddArgs) -> Result<Outcome> {
+    args.dirs.prepare()?;
+    let _lock = lock_staging(&args.dirs)?;
+    let mut builders = load_builders(&args.dirs)?;
+    let before = builders.clone();
 
     for file in args.files {
         let raw = fs::read(file)?;
-        add_builder(&mut builders, raw)?;
+        add_builder(&mut builders, raw, args.network, args.skip_unchanged)?;
     }
     if builders.len() == 0 && !atty::is(Stream::Stdin) {
         let mut raw = Vec::new();
         io::stdin().read_to_end(&mut raw).map_err(|_e| {
-            io::Error::new(io::ErrorKind::InvalidData, "Nothing to add")
+            RegistryError::Staging(String::from("Nothing to add"))
         })?;
-        add_builder(&mut builders, raw)?;
+        add_builder(&mut builders, raw, args.network, args.skip_unchanged)?;
     }
 
-    save_builders(&builders, &args.c)
+    if builders == before {
+        println!("Nothing to add");
+        return Ok(Outcome::NothingToDo);
+    }
+    if !args.no_names {
+        record_names(&args.dirs, &builders)?;
+    }
+    save_builders(&builders, &args.dirs)?;
+    history::record(&args.dirs, "add", &new_entries(&before, &builders), history::Effect::Staged(&builders),
+                    args.max_history)?;
+    Ok(Outcome::Done)
+}
+
+/// Entries of `after` that aren't staged the same way in `before`, by space
+fn new_entries(before: &HashMap<String, TransactionBuilder>, after: &HashMap<String, TransactionBuilder>)
+    -> BTreeMap<String, TransactionBuilder> {
+    after.iter().filter_map(|(space, builder)| {
+        let mut builder = builder.clone();
+        if let Some(old) = before.get(space) {
+            builder.transactions.retain(|tx| !old.transactions.contains(tx));
+        }
+        (!builder.transactions.is_empty()).then(|| (space.clone(), builder))
+    }).collect()
 }
 
-fn add_builder(builders: &mut HashMap<String, TransactionBuilder>, raw: Vec<u8>) -> Result<(), Error> {
+/// Merges a builder JSON file into the staged builders. With
+/// `skip_unchanged` only new and changed entries are merged and the delta
+/// of each space is printed.
+fn add_builder(builders: &mut HashMap<String, TransactionBuilder>, raw: Vec<u8>, network: Network,
+               skip_unchanged: bool) -> Result<()> {
     let user_builder : HashMap<String, TransactionBuilder> = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
-        io::Error::new(io::ErrorKind::InvalidData, "could not parse user tx")
+        RegistryError::Staging(String::from("could not parse user tx"))
     })?;
 
     for (space, user_builder) in user_builder {
+        if let Some(bound) = user_builder.space().filter(|bound| *bound != space) {
+            return Err(RegistryError::Staging(
+                format!("transactions listed under @{} are signed for @{}", space, bound)));
+        }
         let builder = builders.entry(space.clone()).or_insert_with(|| {
-            TransactionBuilder::new()
+            TransactionBuilder::with_network(network)
         });
-        builder.merge(user_builder).map_err(|_e| {
-            io::Error::new(io::ErrorKind::InvalidData, "unable to merge user tx")
+        if !skip_unchanged {
+            builder.merge(user_builder).map_err(|e| {
+                RegistryError::Staging(format!("unable to merge user tx: {}", e))
+            })?;
+            continue;
+        }
+        let delta = builder.merge_changed(user_builder).map_err(|e| {
+            RegistryError::Staging(format!("unable to merge user tx: {}", e))
         })?;
+        println!("@{}: {} new, {} changed, {} unchanged", space, delta.added, delta.changed, delta.unchanged);
     }
     Ok(())
 }
 
-type ZKPayload = Vec<Vec<u8>>;
-type TXSet = Vec<u8>;
+/// Checks every transaction of `builder` against the committed database of
+/// `space`, the result of each transaction in order. Witnesses are checked
+/// on all cores with `parallel`.
+fn verify_builder(pool: &mut DatabasePool, space: &str, builder: &TransactionBuilder, network: Network,
+                  parallel: bool) -> Result<Vec<std::result::Result<(), String>>> {
+    let mut store = pool.get(space)?.map(LocalStore::new);
+    // owners are read up front, the store can't be shared across threads
+    let mut owners = Vec::with_capacity(builder.transactions.len());
+    for entry in &builder.transactions {
+        owners.push(match store.as_mut() {
+            Some(store) => store.get(entry.sort_key().as_bytes())?,
+            None => None,
+        });
+    }
+    let verify = |(entry, current): (&Transaction, Option<Vec<u8>>)| {
+        match (entry.witness.is_empty(), current) {
+            (true, Some(_)) => Err(String::from("already registered")),
+            (true, None) => entry.verify(space, network, None).map_err(|e| e.to_string()),
+            (false, None) => Err(String::from("not registered")),
+            (false, Some(value)) => match split_owner_value(&value) {
+                Some((owner, _)) => entry.verify(space, network, Some(&owner))
+                    .map_err(|e| e.to_string()),
+                None => Err(String::from("unsupported owner type")),
+            },
+        }
+    };
+    Ok(if parallel {
+        builder.transactions.par_iter().zip(owners).map(verify).collect()
+    } else {
+        builder.transactions.iter().zip(owners).map(verify).collect()
+    })
+}
 
-fn prepare_zk_input(working_dir: &Option<String>) -> Result<(ZKPayload, HashMap<String, TXSet>), Error> {
-    let builders = load_builders(working_dir)?;
-    let mut payload : ZKPayload = Vec::with_capacity(builders.len());
-    let mut tx_set : HashMap<String, TXSet> = HashMap::with_capacity(builders.len());
+/// Checks every transaction of the given builders against the committed
+/// databases: registrations must be for names that don't exist yet, anything
+/// else must be signed by the name's current owner. Nothing is staged.
+fn check(args: CheckArgs) -> Result<()> {
+    let mut raws = Vec::new();
+    for file in &args.files {
+        raws.push(fs::read(file)?);
+    }
+    if raws.is_empty() {
+        let mut raw = Vec::new();
+        io::stdin().read_to_end(&mut raw)?;
+        raws.push(raw);
+    }
 
-    for (space, builder) in builders {
-        let raw = builder.build(space.as_str()).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData, format!("could not build tx set: {}", e))
+    let start = Instant::now();
+    let mut pool = DatabasePool::new(&args.dirs)?;
+    let mut checked = 0;
+    let mut failed = 0;
+    for raw in raws {
+        let builders : BTreeMap<String, TransactionBuilder> = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
+            RegistryError::Validation(String::from("could not parse user tx"))
         })?;
-        let db_filename = format!("{}.sdb", space);
-        let raw = tx_set.entry(space.clone()).or_insert_with(|| {
-            raw
-        });
+        for (space, builder) in builders {
+            if builder.network() != Some(args.network) {
+                return Err(RegistryError::Validation(
+                    format!("transactions for @{} are not for {}", space, args.network)));
+            }
+            println!("@{}", space);
+            let results = verify_builder(&mut pool, &space, &builder, args.network, args.parallel_verify)?;
 
-        let path = get_working_dir(working_dir)?.join(db_filename);
+            for (entry, result) in builder.transactions.iter().zip(results) {
+                checked += 1;
+                match result {
+                    Ok(()) => println!("\t{:<24} ok", entry.name),
+                    Err(e) => {
+                        println!("\t{:<24} FAILED: {}", entry.name, e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+    println!("Checked {} transactions in {:.2?}, {} ok, {} failed",
+             checked, start.elapsed(), checked - failed, failed);
 
-        if !std::path::Path::new(path.to_str().unwrap()).exists() {
-            // we don't need to prove initial state
-            continue;
+    if failed > 0 {
+        return Err(RegistryError::Validation(
+            format!("{} transactions do not verify against the committed owners", failed)));
+    }
+    Ok(())
+}
+
+/// Local index from hex encoded subspace and space hashes back to the names
+/// they were staged with. Only hashes are committed, this exists purely so
+/// output can show readable names.
+type NameIndex = BTreeMap<String, String>;
+
+fn load_names(dirs: &DirArgs) -> Result<NameIndex> {
+    let path = dirs.state_dir()?.join(NAMES_FILE);
+    if !path.exists() {
+        return Ok(NameIndex::new());
+    }
+    let raw = fs::read(path)?;
+    let names = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
+        RegistryError::Staging(format!("could not parse {}", NAMES_FILE))
+    })?;
+    Ok(names)
+}
+
+fn record_names(dirs: &DirArgs, builders: &HashMap<String, TransactionBuilder>) -> Result<()> {
+    let mut names = load_names(dirs)?;
+    let len = names.len();
+    for (space, builder) in builders {
+        names.entry(hex::encode(Sha256::digest(space.as_bytes())))
+            .or_insert_with(|| space.clone());
+        for entry in &builder.transactions {
+            names.entry(hex::encode(entry.sort_key()))
+                .or_insert_with(|| entry.name.clone());
         }
+    }
+    if names.len() == len {
+        return Ok(());
+    }
 
-        // create subtree
-        let reader = TransactionReader(raw.as_slice());
+    let str = serde_json::to_string_pretty(&names).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize names"))
+    })?;
+    fs::write(dirs.state_dir()?.join(NAMES_FILE), str)?;
+    Ok(())
+}
 
-        let keys = reader.iter().map(|t| t.subspace_hash.try_into().map_err(
-            |_| io::Error::new(io::ErrorKind::InvalidData, "invalid subspace hash")
-        )).collect::<Result<Vec<Hash>, io::Error>>()?;
+/// Hex encodes a hash, followed by its name if the index knows it
+fn display_hash(names: &NameIndex, hash: &[u8]) -> String {
+    let hex = hex::encode(hash);
+    match names.get(&hex) {
+        Some(name) => format!("{} ({})", hex, name),
+        None => hex,
+    }
+}
 
-        let db = Database::open(path.to_str().unwrap())?;
+/// Databases opened during a single run, keyed by space, so commands that
+/// touch a space several times only open its `.sdb` once. Handles are
+/// shared by reads and writes, each read begins a fresh snapshot and sees
+/// everything committed through the same handle.
+struct DatabasePool {
+    dir: PathBuf,
+    databases: HashMap<String, Database<Sha256Hasher>>,
+}
 
-        let mut snapshot = db.begin_read()?;
-        let subtree = snapshot.prove(&keys, ProofType::Standard).map_err(|e| {
-            io::Error::new(io::ErrorKind::InvalidData,
-                                format!("could not generate subtree: {}", e))
+impl DatabasePool {
+    fn new(dirs: &DirArgs) -> Result<Self> {
+        Ok(Self { dir: dirs.db_dir()?, databases: HashMap::new() })
+    }
+
+    /// Database of `space`, or `None` if it doesn't have one yet
+    fn get(&mut self, space: &str) -> Result<Option<&Database<Sha256Hasher>>> {
+        if !self.databases.contains_key(space) && !self.path(space).exists() {
+            return Ok(None);
+        }
+        self.open(space).map(Some)
+    }
+
+    /// Database of `space`, created if it doesn't exist
+    fn open(&mut self, space: &str) -> Result<&Database<Sha256Hasher>> {
+        if !self.databases.contains_key(space) {
+            let db = open_db(&self.path(space))?;
+            self.databases.insert(String::from(space), db);
+        }
+        Ok(&self.databases[space])
+    }
+
+    /// Store of `space`, backed by its database, created if it doesn't exist
+    #[cfg(feature = "zkvm")]
+    fn store(&mut self, space: &str) -> Result<LocalStore<'_>> {
+        self.open(space).map(LocalStore::new)
+    }
+
+    fn path(&self, space: &str) -> PathBuf {
+        self.dir.join(format!("{}.sdb", space))
+    }
+}
+
+/// Opens the database at `path`. spacedb only takes UTF-8 paths, anything
+/// else is an error rather than a panic.
+fn open_db(path: &Path) -> Result<Database<Sha256Hasher>> {
+    let path_str = path.to_str().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not valid UTF-8", path.display()))
+    })?;
+    Ok(Database::open(path_str)?)
+}
+
+/// Splits a public key leaf value into its owner and memo
+fn split_owner_value(value: &[u8]) -> Option<(Owner, &[u8])> {
+    match value.split_first() {
+        Some((&VALUE_TYPE_PUBLIC_KEY, value)) if value.len() >= OWNER_SIZE => {
+            let (owner, memo) = value.split_at(OWNER_SIZE);
+            Some((Owner::try_from(owner).ok()?, memo))
+        }
+        _ => None,
+    }
+}
+
+fn export(args: ExportArgs) -> Result<()> {
+    println!("{}", export_space(&args.dirs, &args.space)?);
+    Ok(())
+}
+
+/// Committed owners of `space` as the JSON `import` reads
+fn export_space(dirs: &DirArgs, space: &str) -> Result<String> {
+    let path = dirs.db_dir()?.join(format!("{}.sdb", space));
+    if !path.exists() {
+        return Err(RegistryError::Validation(format!("no database for @{}", space)));
+    }
+
+    let db = open_db(&path)?;
+    let mut snapshot = db.begin_read()?;
+
+    let mut owners = BTreeMap::new();
+    for entry in snapshot.iter() {
+        let (key, value) = entry?;
+        let (owner, memo) = split_owner_value(&value).ok_or_else(|| {
+            RegistryError::Validation(format!("unsupported owner type for {}", hex::encode(key)))
         })?;
+        let mut exported = serde_json::json!({ "owner": hex::encode(owner.as_bytes()) });
+        if !memo.is_empty() {
+            exported["memo"] = serde_json::Value::String(hex::encode(memo));
+        }
+        owners.insert(hex::encode(key), exported);
+    }
+
+    serde_json::to_string_pretty(&owners).map_err(|_e| {
+        RegistryError::Validation(String::from("unable to serialize owners"))
+    })
+}
+
+fn import(args: ImportArgs) -> Result<()> {
+    args.dirs.prepare()?;
+    let raw = match args.file {
+        Some(file) => fs::read(file)?,
+        None => {
+            let mut raw = Vec::new();
+            io::stdin().read_to_end(&mut raw)?;
+            raw
+        }
+    };
+    let (imported, root) = import_space(&args.dirs, &args.space, &raw, args.force)?;
+    println!("Imported {} entries into @{} (root {})", imported, args.space, hex::encode(root));
+    Ok(())
+}
 
-        let mut subtree_raw = bincode::encode_to_vec(&subtree, bincode::config::standard())
-            .map_err(|e| { io::Error::new(io::ErrorKind::InvalidData,
-                                format!("could not encode subtree: {}", e))
+/// Seeds the database of `space` from exported owners, the number of
+/// entries imported and the resulting root
+fn import_space(dirs: &DirArgs, space: &str, raw: &[u8], force: bool) -> Result<(usize, Hash)> {
+    let owners : BTreeMap<String, serde_json::Value> = serde_json::from_slice(raw).map_err(|_e| {
+        RegistryError::Validation(String::from("could not parse exported owners"))
+    })?;
+
+    let mut entries = Vec::with_capacity(owners.len());
+    for (key, exported) in owners {
+        let owner = exported["owner"].as_str()
+            .and_then(|owner| Owner::try_from(hex::decode(owner).ok()?.as_slice()).ok())
+            .ok_or_else(|| {
+                RegistryError::Validation(format!("invalid owner for {}", key))
+            })?;
+        // the memo is left out if there is none
+        let memo = match &exported["memo"] {
+            serde_json::Value::Null => Vec::new(),
+            memo => memo.as_str().and_then(|memo| hex::decode(memo).ok()).ok_or_else(|| {
+                RegistryError::Validation(format!("invalid memo for {}", key))
+            })?,
+        };
+        let key : Hash = hex::decode(&key).ok().and_then(|k| k.try_into().ok()).ok_or_else(|| {
+            RegistryError::Validation(format!("invalid subspace hash {}", key))
         })?;
+        entries.push((key, (owner, memo)));
+    }
 
-        subtree_raw.extend_from_slice(raw.as_slice());
-        payload.push(subtree_raw);
+    let path = dirs.db_dir()?.join(format!("{}.sdb", space));
+    if path.exists() {
+        let db = open_db(&path)?;
+        let is_empty = db.begin_read()?.iter().next().is_none();
+        drop(db);
+        if !is_empty && !force {
+            return Err(RegistryError::Validation(
+                format!("@{} already has a non-empty database (use --force to replace it)", space)));
+        }
+        fs::remove_file(&path)?;
     }
 
-    Ok((payload, tx_set))
+    let db = open_db(&path)?;
+    let mut store = LocalStore::new(&db);
+    for (key, (owner, memo)) in entries.iter() {
+        store.insert(*key, owner_value(owner, memo))?;
+    }
+    store.commit()?;
+    Ok((entries.len(), store.read_root()?))
 }
 
-fn prove(working_dir : &Option<String>) -> Result<(Vec<Commitment>, HashMap<String, TXSet>), Error> {
-    // Initialize tracing. In order to view logs, run `RUST_LOG=info cargo run`
-    env_logger::init();
-    let (zk_input, tx_set) = prepare_zk_input(working_dir)?;
-    if zk_input.is_empty() {
-        return Ok((Vec::new(), tx_set));
+/// Moves the committed owners of `old_space` to `new_space` in two steps.
+///
+/// Without `--signed` every owner is written to `migrate-<new_space>.json`
+/// as a transfer to itself, unsigned. Each owner signs theirs under the new
+/// space hash, which is what separates the two domains, e.g. with
+/// `subs sign-request` and `subs attach-witness`. Given the signed file,
+/// every witness is checked against the committed owner and the owners are
+/// staged as registrations in the new space, the next commit proves its
+/// tree. Leaves only store subspace hashes, every name has to be in the
+/// names index to be migrated.
+fn migrate(args: MigrateArgs) -> Result<()> {
+    args.dirs.prepare()?;
+    let _lock = lock_staging(&args.dirs)?;
+    let db_dir = args.dirs.db_dir()?;
+    let old_path = db_dir.join(format!("{}.sdb", args.old_space));
+    if !old_path.exists() {
+        return Err(RegistryError::Validation(format!("no database for @{}", args.old_space)));
+    }
+    let new_path = db_dir.join(format!("{}.sdb", args.new_space));
+    if new_path.exists() && open_db(&new_path)?.begin_read()?.iter().next().is_some() {
+        return Err(RegistryError::Validation(
+            format!("@{} already has a non-empty database", args.new_space)));
     }
 
-    let env = ExecutorEnv::builder().write(&zk_input).unwrap().build().unwrap();
-    let prover = default_prover();
+    let mut builders = load_builders(&args.dirs)?;
+    if builders.contains_key(&args.new_space) {
+        return Err(RegistryError::Staging(format!("@{} already has staged changes", args.new_space)));
+    }
 
-    println!("Proving Started ...");
-    println!("-------------------------------------");
-    println!("- Using Prover: {}", prover.get_name());
+    let names = load_names(&args.dirs)?;
+    let db = open_db(&old_path)?;
+    let mut snapshot = db.begin_read()?;
+    let mut owners = BTreeMap::new();
+    let mut unknown = 0;
+    for entry in snapshot.iter() {
+        let (key, value) = entry?;
+        let key = hex::encode(key);
+        let (owner, memo) = split_owner_value(&value).ok_or_else(|| {
+            RegistryError::Validation(format!("unsupported owner type for {}", key))
+        })?;
+        match names.get(&key) {
+            Some(name) => owners.insert(name.clone(), (owner, memo.to_vec())),
+            None => {
+                unknown += 1;
+                continue;
+            }
+        };
+    }
+    if unknown > 0 {
+        return Err(RegistryError::Validation(
+            format!("{} subspaces of @{} are not in {}, their names are needed to migrate them",
+                    unknown, args.old_space, NAMES_FILE)));
+    }
+
+    let signed_path = match &args.signed {
+        Some(path) => path,
+        None => {
+            let mut unsigned = TransactionBuilder::with_network(args.network);
+            for (name, (owner, memo)) in &owners {
+                unsigned.add(Transaction::new(name, *owner).with_memo(memo.clone()), None).map_err(|e| {
+                    RegistryError::Staging(format!("could not stage {}: {}", name, e))
+                })?;
+            }
+            let path = args.dirs.state_dir()?.join(format!("migrate-{}.json", args.new_space));
+            let str = serde_json::to_string_pretty(&HashMap::from([(&args.new_space, unsigned)])).map_err(|_e| {
+                RegistryError::Staging(String::from("unable to serialize builders"))
+            })?;
+            fs::write(&path, str)?;
+            println!("Wrote {} transfers from @{} to {}, each owner has to sign theirs under @{}",
+                     owners.len(), args.old_space, path.display(), args.new_space);
+            println!("  (use \"registry migrate {} {} --signed {}\" once they're signed)",
+                     args.old_space, args.new_space, path.display());
+            return Ok(());
+        }
+    };
 
-    // Produce a receipt by proving the specified ELF binary.
-    let start = std::time::Instant::now();
-    let receipt = prover.prove(env, SUBSPACER_ELF).map_err(|e| {
-        io::Error::new(io::ErrorKind::InvalidData,
-                            format!("could not prove elf: {}", e))
+    let mut signed : HashMap<String, TransactionBuilder> = serde_json::from_slice(&fs::read(signed_path)?).map_err(|_e| {
+        RegistryError::Staging(format!("could not parse {}", signed_path))
+    })?;
+    let signed_builder = signed.remove(&args.new_space).ok_or_else(|| {
+        RegistryError::Staging(format!("{} has no transfers for @{}", signed_path, args.new_space))
     })?;
-    println!("- Took: {:?}", start.elapsed());
+    if signed_builder.network() != Some(args.network) {
+        return Err(RegistryError::Staging(format!("{} is not for {}", signed_path, args.network)));
+    }
+    if signed_builder.transactions.len() != owners.len() {
+        return Err(RegistryError::Validation(format!("{} has {} transfers, @{} has {} owners",
+            signed_path, signed_builder.transactions.len(), args.old_space, owners.len())));
+    }
 
-    receipt.verify(SUBSPACER_ID).map_err(|e| {
-        io::Error::new(std::io::ErrorKind::InvalidData,
-                            format!("could not verify receipt: {}", e))
+    // the new tree starts out empty, so verified owners go in as registrations
+    let mut builder = TransactionBuilder::with_network(args.network);
+    for entry in &signed_builder.transactions {
+        let (owner, memo) = owners.get(&entry.name).ok_or_else(|| {
+            RegistryError::Validation(format!("{} is not a subspace of @{}", entry.name, args.old_space))
+        })?;
+        if entry.owner != *owner || entry.memo != *memo {
+            return Err(RegistryError::Validation(
+                format!("{} must keep its owner and memo when migrated", entry.name)));
+        }
+        entry.verify(&args.new_space, args.network, Some(owner)).map_err(|e| {
+            RegistryError::Validation(format!("@{} rejected: {}", args.new_space, e))
+        })?;
+        builder.add(Transaction::new(&entry.name, *owner).with_memo(memo.clone()), None).map_err(|e| {
+            RegistryError::Staging(format!("could not stage {}: {}", entry.name, e))
+        })?;
+    }
+
+    let count = builder.transactions.len();
+    builders.insert(args.new_space.clone(), builder);
+    record_names(&args.dirs, &builders)?;
+    save_builders(&builders, &args.dirs)?;
+    // the registrations carry no witness, the record keeps the signatures
+    history::record(&args.dirs, "migrate", &BTreeMap::from([(args.new_space.clone(), signed_builder)]),
+                    history::Effect::Staged(&builders), args.max_history)?;
+
+    println!("Staged {} registrations from @{} in @{}", count, args.old_space, args.new_space);
+    println!("  (use \"registry commit\" to prove the migration)");
+    Ok(())
+}
+
+/// Proves a single key of a space's database, serialized as JSON with the
+/// root it was proven against and the hex encoded bincode subtree
+fn prove_inclusion(args: ProveInclusionArgs) -> Result<()> {
+    // hashed the way subs hashes it, so unicode and punycode forms agree
+    let (name, space) = verify_name(&args.subspace).map_err(|e| {
+        RegistryError::Validation(format!("{}: {}", args.subspace, e))
     })?;
 
-    println!("- Receipt Verified\n");
+    let path = args.dirs.db_dir()?.join(format!("{}.sdb", space));
+    if !path.exists() {
+        return Err(RegistryError::Validation(format!("no database for @{}", space)));
+    }
 
-    let output : Vec<Commitment> = receipt.journal.decode().map_err(|e| {
-        io::Error::new(io::ErrorKind::InvalidData,
-                            format!("could not decode receipt: {}", e))
+    let key : Hash = Sha256::digest(name.as_bytes()).into();
+    let db = open_db(&path)?;
+    let mut snapshot = db.begin_read()?;
+    if snapshot.get(&key)?.is_none() {
+        return Err(RegistryError::Validation(format!("{}@{} is not committed", name, space)));
+    }
+    let subtree = snapshot.prove(&[key], ProofType::Standard).map_err(|e| {
+        RegistryError::Proving(format!("could not generate subtree: {}", e))
     })?;
+    let subtree_raw = bincode::encode_to_vec(&subtree, bincode::config::standard()).map_err(|e| {
+        RegistryError::Proving(format!("could not encode subtree: {}", e))
+    })?;
+
+    let mut proof = BTreeMap::new();
+    proof.insert("space", space.clone());
+    proof.insert("subspace", hex::encode(key));
+    proof.insert("root", hex::encode(snapshot.root()?));
+    proof.insert("proof", hex::encode(subtree_raw));
 
-    // save receipt to output arg
-    let raw_receipt = bincode::serde::encode_to_vec(&receipt, bincode::config::standard())
-        .map_err(|e| {
-        io::Error::new(io::ErrorKind::InvalidData,
-                            format!("could not serialize receipt: {}", e))
+    let str = serde_json::to_string_pretty(&proof).map_err(|_e| {
+        RegistryError::Proving(String::from("unable to serialize proof"))
     })?;
+    match args.output {
+        Some(output) => fs::write(output, str)?,
+        None => println!("{}", str),
+    }
+    Ok(())
+}
 
-    let path = get_working_dir(working_dir)?.join("receipt.bin");
+/// Checks that a proof written by `prove-inclusion` has the given root and
+/// prints the owner it proves for the subspace
+fn verify_inclusion(args: VerifyInclusionArgs) -> Result<()> {
+    let invalid = |msg: String| RegistryError::Validation(msg);
 
-    fs::write(path.to_str().unwrap(), raw_receipt)?;
+    let raw = fs::read(&args.file)?;
+    let proof : BTreeMap<String, String> = serde_json::from_slice(raw.as_slice())
+        .map_err(|_e| invalid(format!("could not parse {}", args.file)))?;
+    let field = |name: &str| -> Result<Vec<u8>> {
+        let value = proof.get(name).ok_or_else(|| invalid(format!("proof is missing {}", name)))?;
+        hex::decode(value).map_err(|_e| invalid(format!("proof has an invalid {}", name)))
+    };
 
-    Ok((output, tx_set))
-}
+    let expected_root : Hash = hex::decode(&args.root).ok().and_then(|r| r.try_into().ok())
+        .ok_or_else(|| invalid(format!("invalid root {}", args.root)))?;
+    let key : Hash = field("subspace")?.try_into()
+        .map_err(|_e| invalid(String::from("proof has an invalid subspace")))?;
+    let (subtree, _) : (SubTree<Sha256Hasher>, usize) =
+        bincode::decode_from_slice(field("proof")?.as_slice(), bincode::config::standard())
+            .map_err(|e| invalid(format!("could not decode subtree: {}", e)))?;
 
+    let root = subtree.root()?;
+    if root != expected_root {
+        return Err(invalid(format!("proof is for root {}, expected {}",
+                                   hex::encode(root), args.root)));
+    }
+
+    let value = subtree.iter().find(|(k, _)| **k == key).map(|(_, v)| v)
+        .ok_or_else(|| invalid(format!("proof does not include {}", hex::encode(key))))?;
+    let owner = match value.split_first() {
+        Some((&VALUE_TYPE_PUBLIC_KEY, owner)) if owner.len() >= OWNER_SIZE => owner,
+        _ => return Err(invalid(format!("unsupported owner type for {}", hex::encode(key)))),
+    };
+    let (owner, memo) = owner.split_at(OWNER_SIZE);
 
-fn commit(args : CommitArgs) -> Result<(), Error> {
-    let uncommitted_path = get_working_dir(&args.c)?.join(STAGING_FILE);
-    if !std::path::Path::new(uncommitted_path.to_str().unwrap()).exists() {
-        return Err(Error::from(io::Error::new(io::ErrorKind::InvalidData, "No changes to prove and commit")));
+    if let Some(expected) = args.owner {
+        if !hex::encode(owner).eq_ignore_ascii_case(&expected) {
+            return Err(invalid(format!("{} is owned by {}, expected {}",
+                                       hex::encode(key), hex::encode(owner), expected)));
+        }
     }
 
-    let (output, tx_set) = prove(&args.c)?;
+    println!("Subspace: {}", hex::encode(key));
+    println!("Root: {}", hex::encode(root));
+    println!("Owner: {}", hex::encode(owner));
+    if !memo.is_empty() {
+        println!("Memo: {}", hex::encode(memo));
+    }
+    Ok(())
+}
 
-    println!("Journal Output");
-    println!("-------------------------------------");
-    println!("Total Spaces: {}\n", output.len());
-    for commitment in output.iter() {
-        println!("\tID: {}", hex::encode(commitment.space));
-        println!("\tMerkle Root Changes: ");
-        println!("\t- Initial: {}", hex::encode(commitment.initial_root));
-        println!("\t- Final: {}", hex::encode(commitment.final_root));
-        println!("\n\n")
+fn format_version() -> Result<()> {
+    println!("Format version: {}", FORMAT_VERSION);
+    println!("Networks: {} ({:#04x}), {} ({:#04x})",
+             Network::Mainnet, Network::Mainnet as u8, Network::Testnet, Network::Testnet as u8);
+    println!("Witness types:");
+    for (kind, name) in WITNESS_TYPES {
+        println!("  {:#04x} {}", kind, name);
     }
+    println!("  {:#04x} memo flag", WITNESS_FLAG_MEMO);
+    println!("  {:#04x} validity window flag", WITNESS_FLAG_WINDOW);
+    Ok(())
+}
 
-    println!("Committing changes ...");
+fn decode(args: DecodeArgs) -> Result<()> {
+    let raw = fs::read(args.file)?;
+    if raw.len() < HEADER_SIZE {
+        return Err(RegistryError::Validation(
+            format!("blob is {} bytes, shorter than the {} byte header", raw.len(), HEADER_SIZE)));
+    }
 
-    let path = get_working_dir(&args.c)?;
-    for (space, raw) in tx_set {
-        let filename = format!("{}.sdb", space);
-        let path = path.join(filename);
-        let db = Database::open(path.to_str().unwrap())?;
-        let mut tx = db.begin_write().unwrap();
-        let reader = TransactionReader(raw.as_slice());
+    let reader = TransactionReader::new(raw.as_slice());
+    let network = Network::try_from(reader.network())
+        .map(|n| n.to_string())
+        .unwrap_or_else(|_| format!("unknown ({:#04x})", reader.network()));
 
-        for t in reader.iter() {
-            let key = t.subspace_hash.try_into().unwrap();
-            tx.insert(key, t.owner.to_vec()).unwrap();
+    let names = load_names(&args.dirs)?;
+
+    println!("Version: {}", reader.version());
+    println!("Network: {}", network);
+    println!("Space: {}\n", display_hash(&names, reader.space_hash().as_bytes()));
+    println!("{:<5} {:<64} {:<64} {:<9} {:<6} Name", "#", "Subspace", "Owner", "Witness", "Length");
+
+    let mut malformed = None;
+    for (i, entry) in reader.entries().enumerate() {
+        match entry {
+            Ok(entry) => {
+                let witness_type = match parse_witness(entry.witness()) {
+                    Ok(w) => {
+                        let kind = match w.kind {
+                            WITNESS_TYPE_NONE => String::from("none"),
+                            WITNESS_TYPE_SIGNATURE => String::from("sig"),
+                            WITNESS_TYPE_ROTATION => String::from("rotate"),
+                            WITNESS_TYPE_SCHNORR => String::from("schnorr"),
+                            t => format!("{:#04x}", t),
+                        };
+                        let kind = if w.memo.is_empty() { kind } else { format!("{}+memo", kind) };
+                        if w.window.is_none() { kind } else { format!("{}+window", kind) }
+                    }
+                    Err(_) => String::from("malformed"),
+                };
+                let subspace = hex::encode(entry.subspace_hash());
+                let name = names.get(&subspace).map(String::as_str).unwrap_or("-");
+                println!("{:<5} {:<64} {:<64} {:<9} {:<6} {}", i,
+                         subspace, hex::encode(entry.owner()),
+                         witness_type, entry.witness().len(), name);
+            }
+            Err(e) => {
+                println!("{:<5} malformed: {}", i, e);
+                malformed = Some(e);
+            }
         }
-        tx.commit()?;
     }
 
-    // remove uncommitted.json
-    let input = path.join(STAGING_FILE);
-    if std::path::Path::new(input.to_str().unwrap()).exists() {
-        fs::remove_file(input)?;
+    if let Some(e) = malformed {
+        return Err(RegistryError::Validation(format!("blob contains a malformed entry: {}", e)));
     }
-
-    println!("Done!");
+    println!("\nRegistrations: {}, Updates: {}", reader.registrations().count(), reader.updates().count());
     Ok(())
 }
 
-fn main() -> Result<(), Error> {
-    let args = Cli::parse();
-    match args {
+fn main() -> ExitCode {
+    let options = Options::parse();
+    match run(options.command) {
+        Ok(Outcome::Done) => ExitCode::SUCCESS,
+        Ok(Outcome::NothingToDo) => ExitCode::from(EXIT_NOTHING_TO_DO),
+        Err(e) if options.json => {
+            eprintln!("{}", serde_json::json!({ "error": e.to_string(), "kind": e.kind() }));
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(command: Cli) -> Result<Outcome> {
+    match command {
         Cli::Status(args) => {
-            status(args)?;
+            return status(args);
         }
         Cli::Add(args) => {
-            add(args)?;
+            return add(args);
         }
+        Cli::Check(args) => {
+            check(args)?;
+        }
+        #[cfg(feature = "zkvm")]
         Cli::Commit(args) => {
-            commit(args)?;
+            return prove::commit(args);
+        }
+        #[cfg(feature = "zkvm")]
+        Cli::Prove(args) => {
+            return prove::prove_only(args);
+        }
+        #[cfg(feature = "zkvm")]
+        Cli::Apply(args) => {
+            prove::apply(args)?;
         }
         Cli::Issue(_) => {}
+        #[cfg(feature = "zkvm")]
+        Cli::Verify(args) => {
+            prove::verify(args)?;
+        }
+        Cli::Export(args) => {
+            export(args)?;
+        }
+        Cli::Import(args) => {
+            import(args)?;
+        }
+        Cli::Decode(args) => {
+            decode(args)?;
+        }
+        Cli::Migrate(args) => {
+            migrate(args)?;
+        }
+        Cli::ProveInclusion(args) => {
+            prove_inclusion(args)?;
+        }
+        Cli::VerifyInclusion(args) => {
+            verify_inclusion(args)?;
+        }
+        Cli::FormatVersion => {
+            format_version()?;
+        }
+        Cli::Log(args) => {
+            history::log(&args.dirs, args.limit)?;
+        }
+        Cli::History(args) => {
+            history::roots(&args.dirs, &args.space)?;
+        }
+        #[cfg(feature = "zkvm")]
+        Cli::Bench(args) => {
+            let seed = match args.seed {
+                Some(seed) => hex::decode(&seed).ok().and_then(|s| s.try_into().ok()).ok_or_else(|| {
+                    RegistryError::Validation(String::from("seed must be 32 bytes hex"))
+                })?,
+                None => [0u8; 32],
+            };
+            bench::bench(&args.sizes, args.updates, &seed)?;
+        }
+        #[cfg(feature = "server")]
+        Cli::Serve(args) => {
+            server::serve(args.listen.as_str(), &args.dirs, args.network, args.max_history)?;
+        }
     }
 
-    Ok(())
+    Ok(Outcome::Done)
+}
+
+/// `c`, or the current directory. It has to be a directory if it exists,
+/// it's only created by `DirArgs::prepare`.
+fn get_working_dir(c : Option<&PathBuf>) -> Result<PathBuf, io::Error> {
+    let path = c.cloned().unwrap_or_else(|| PathBuf::from("."));
+    if path.exists() && !path.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+            format!("{} is not a directory", path.display())));
+    }
+    Ok(path)
+}
+
+/// Creates `path` if needed and checks a file can be created in it. Mode
+/// bits don't tell whether this user may write, e.g. to a directory owned
+/// by root, so a file is created and removed again.
+fn ensure_writable(path: &Path) -> Result<(), io::Error> {
+    // unique per call, several threads may check the same directory at once
+    static PROBES: AtomicUsize = AtomicUsize::new(0);
+    fs::create_dir_all(path)?;
+    let probe = path.join(format!(".write-test-{}-{}", std::process::id(), PROBES.fetch_add(1, Ordering::Relaxed)));
+    fs::OpenOptions::new().write(true).create_new(true).open(&probe).map_err(|e| {
+        io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is not writable: {}", path.display(), e))
+    })?;
+    fs::remove_file(&probe)
 }
 
-fn get_working_dir(c : &Option<String>) -> Result<PathBuf, io::Error> {
-    let mut path_prefix = PathBuf::new();
-    if let Some(output) = c {
-        path_prefix.push(output);
-        if !path_prefix.exists() {
-            fs::create_dir_all(&path_prefix)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs(path: &Path) -> DirArgs {
+        DirArgs { c: Some(path.to_path_buf()), db_dir: None, state_dir: None }
+    }
+
+    /// Builder JSON staging `name` under @foo
+    fn builder_file(dir: &Path, name: &str) -> String {
+        let json = format!(r#"{{"foo": {{"version": {}, "transactions": [{{"name": "{}", "owner": "{}"}}]}}}}"#,
+                           FORMAT_VERSION, name, "11".repeat(OWNER_SIZE));
+        let path = dir.join(format!("{}.json", name));
+        fs::write(&path, json).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn concurrent_adds_are_both_staged() {
+        let dir = tempfile::tempdir().unwrap();
+        let handles: Vec<_> = ["alice", "bob"].into_iter().map(|name| {
+            let args = AddArgs {
+                files: vec![builder_file(dir.path(), name)],
+                dirs: dirs(dir.path()),
+                network: Network::Mainnet,
+                no_names: true,
+                skip_unchanged: false,
+                max_history: 0,
+            };
+            std::thread::spawn(move || add(args).map(|_| ()).map_err(|e| e.to_string()))
+        }).collect();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
         }
 
-        let metadata = fs::metadata(&path_prefix)?;
-        if !metadata.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Path is not a directory"));
+        let builders = load_builders(&dirs(dir.path())).unwrap();
+        let mut names: Vec<_> = builders["foo"].transactions.iter().map(|e| e.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["alice", "bob"]);
+    }
+
+    #[test]
+    fn history_keeps_the_newest_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["alice", "bob"] {
+            add(AddArgs {
+                files: vec![builder_file(dir.path(), name)],
+                dirs: dirs(dir.path()),
+                network: Network::Mainnet,
+                no_names: true,
+                skip_unchanged: false,
+                max_history: 1,
+            }).unwrap();
         }
-    } else {
-        path_prefix.push(".");
+
+        let history = dir.path().join(".history");
+        let files: Vec<_> = fs::read_dir(&history).unwrap().map(|e| e.unwrap().path()).collect();
+        assert_eq!(files.len(), 1);
+        let record: serde_json::Value = serde_json::from_slice(&fs::read(&files[0]).unwrap()).unwrap();
+        let batch = record["builders"]["foo"]["transactions"].as_array().unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0]["name"], "bob");
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let (from, to) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let db = open_db(&from.path().join("foo.sdb")).unwrap();
+        let mut store = LocalStore::new(&db);
+        store.insert([1u8; 32], owner_value(&Owner::from([0x11; OWNER_SIZE]), &[])).unwrap();
+        store.insert([2u8; 32], owner_value(&Owner::from([0x22; OWNER_SIZE]), b"memo")).unwrap();
+        store.commit().unwrap();
+
+        let exported = export_space(&dirs(from.path()), "foo").unwrap();
+        let (imported, root) = import_space(&dirs(to.path()), "foo", exported.as_bytes(), false).unwrap();
+        assert_eq!(imported, 2);
+        assert_eq!(root, store.read_root().unwrap());
     }
-    Ok(path_prefix)
 }
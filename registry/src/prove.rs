@@ -0,0 +1,1405 @@
+// The prove path: building guest input, running the prover, receipts, and
+// the commands that need them. Staging and queries in main.rs don't touch
+// risc0, so they still build without the `zkvm` feature.
+
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, io};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use atty::Stream;
+// These constants represent the RISC-V ELF and the image ID generated by risc0-build.
+// The ELF is used for proving and the ID is used for verification.
+use methods::{
+    SUBSPACER_ELF, SUBSPACER_ID
+};
+use risc0_zkvm::{default_executor, default_prover, BonsaiProver, ExecutorEnv, Prover, Receipt};
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use spacedb::{Hash, Sha256Hasher};
+use spacedb::subtree::SubTree;
+use spacedb::db::Database;
+use spacedb::tx::ProofType;
+use program::builder::TransactionBuilder;
+use program::guest::{
+    encode_tx_set, encode_tx_set_into, encoded_tx_set_len, handle_tx_set, owner_value, parse_witness, Commitment,
+    DEFAULT_MAX_ENTRIES, PROOF_TYPE_EXTENDED, PROOF_TYPE_STANDARD,
+};
+use program::{Network, TransactionReader};
+use crate::{
+    builder_stats, display_hash, load_builders, load_names, lock_staging, record_names,
+    DatabasePool, DirArgs, NameIndex, Outcome, COMMIT_LOG_FILE, STAGING_FILE,
+};
+use crate::error::{RegistryError, Result};
+use crate::history;
+use crate::store::{LocalStore, RegistryStore};
+
+const RECEIPT_FILE: &str = "receipt.bin";
+const RECEIPT_FILE_GZ: &str = "receipt.bin.gz";
+const CACHE_DIR: &str = "receipts";
+const COMMIT_STATE_FILE: &str = "commit.state";
+const TX_SETS_FILE: &str = "txsets.json";
+const ZK_INPUT_FILE: &str = "zkinput.bin";
+const COMMIT_MANIFEST_FILE: &str = "commit-manifest.json";
+const BONSAI_API_URL_ENV: &str = "BONSAI_API_URL";
+const BONSAI_API_KEY_ENV: &str = "BONSAI_API_KEY";
+
+/// Delay before the first retry of a failed proof, doubled on each retry
+const RETRY_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+/// Provers report a panicking guest with this in the error. The text isn't
+/// a stable interface, it's only checked when `is_retryable` can't execute
+/// the input to find out
+const GUEST_PANIC: &str = "panicked";
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct CommitArgs {
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    #[command(flatten)]
+    proving: ProveOptions,
+
+    /// Print the roots the staged changes would produce without proving or committing
+    #[arg(long, short)]
+    dry_run: bool,
+
+    /// Skip the confirmation prompt
+    #[arg(long, short)]
+    yes: bool,
+
+    /// Don't record names in the local names index
+    #[arg(long)]
+    no_names: bool,
+
+    /// Check transactions natively and commit without proving, for trusted setups.
+    /// No receipt is written
+    #[arg(long, conflicts_with_all = ["no_verify_receipt", "compress", "timeout", "retries", "prover", "emit_journal"])]
+    no_proof: bool,
+
+    /// Also write the receipt's journal, the commitments without the proof, to this file
+    #[arg(long)]
+    emit_journal: Option<String>,
+
+    /// Rename uncommitted.json to committed-<unix time>.json after committing instead of deleting it
+    #[arg(long)]
+    keep_staging: bool,
+
+    /// Commit tx sets even if an identical one is recorded in committed.log
+    #[arg(long)]
+    allow_replay: bool,
+
+    /// Records to keep in .history, 0 to keep none
+    #[arg(long, default_value_t = history::DEFAULT_MAX_HISTORY)]
+    max_history: usize,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ProveArgs {
+    #[command(flatten)]
+    dirs: DirArgs,
+
+    #[command(flatten)]
+    proving: ProveOptions,
+}
+
+#[cfg(feature = "async")]
+impl ProveArgs {
+    /// What `registry prove` would run with no options but `--network`
+    pub(crate) fn with_network(dirs: DirArgs, network: Network) -> Self {
+        #[derive(clap::Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            proving: ProveOptions,
+        }
+        let mut proving = <Defaults as clap::Parser>::parse_from(["prove"]).proving;
+        proving.network = network;
+        ProveArgs { dirs, proving }
+    }
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct ApplyArgs {
+    /// Receipt written by prove, defaults to receipt.bin or receipt.bin.gz in the state directory
+    pub(crate) receipt: Option<String>,
+
+    /// Tx sets written by prove, defaults to txsets.json in the state directory
+    #[arg(long)]
+    tx_sets: Option<String>,
+
+    /// Network the tx sets must be for
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Batch size limit the receipt must have been proven with
+    #[arg(long, default_value_t = DEFAULT_MAX_ENTRIES)]
+    max_entries: u32,
+
+    /// Block height the receipt must have been proven at, any if omitted
+    #[arg(long)]
+    height: Option<u32>,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+}
+
+/// How `commit` and `prove` run the prover
+#[derive(clap::Args, Clone)]
+pub struct ProveOptions {
+    /// Skip verifying the receipt after proving, it is still written to receipt.bin
+    #[arg(long)]
+    no_verify_receipt: bool,
+
+    /// Gzip the receipt and write it to receipt.bin.gz
+    #[arg(long)]
+    compress: bool,
+
+    /// Network the guest verifies transactions against
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Give up if proving takes longer than this many seconds, staged changes are kept
+    #[arg(long)]
+    timeout: Option<u64>,
+
+    /// Drop staged registrations for names that are already in the database
+    /// instead of failing the whole proof
+    #[arg(long)]
+    skip_existing: bool,
+
+    /// Kind of subtree proof handed to the guest
+    #[arg(long, value_enum, default_value_t = SubTreeProof::Standard)]
+    proof_type: SubTreeProof,
+
+    /// Proving backend, picked from the environment if omitted
+    #[arg(long, value_enum)]
+    prover: Option<ProverKind>,
+
+    /// Retry failed proofs this many times with exponential backoff,
+    /// a guest that fails on the input is never retried
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+
+    /// Most transactions the guest accepts in a single space's tx set
+    #[arg(long, default_value_t = DEFAULT_MAX_ENTRIES)]
+    max_entries: u32,
+
+    /// Current block height, witnesses with a validity window are only
+    /// accepted if it's inside it
+    #[arg(long, default_value_t = 0)]
+    height: u32,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum ProverKind {
+    Local,
+    Bonsai,
+}
+
+impl ProverKind {
+    /// Fails early if the backend can't be used, rather than once proving starts
+    fn check(self) -> Result<()> {
+        if let ProverKind::Bonsai = self {
+            for var in [BONSAI_API_URL_ENV, BONSAI_API_KEY_ENV] {
+                if std::env::var_os(var).is_none() {
+                    return Err(RegistryError::Proving(
+                        format!("--prover bonsai requires {} to be set", var)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn prover(self) -> Rc<dyn Prover> {
+        match self {
+            #[cfg(feature = "prove")]
+            ProverKind::Local => Rc::new(risc0_zkvm::LocalProver::new("local")),
+            // without the prove feature proving runs in the r0vm binary
+            #[cfg(not(feature = "prove"))]
+            ProverKind::Local => {
+                let r0vm = std::env::var_os("RISC0_SERVER_PATH").unwrap_or_else(|| "r0vm".into());
+                Rc::new(risc0_zkvm::ExternalProver::new("ipc", r0vm))
+            }
+            ProverKind::Bonsai => Rc::new(BonsaiProver::new("bonsai")),
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+pub enum SubTreeProof {
+    Standard,
+    Extended,
+}
+
+impl SubTreeProof {
+    fn proof_type(self) -> ProofType {
+        match self {
+            SubTreeProof::Standard => ProofType::Standard,
+            SubTreeProof::Extended => ProofType::Extended,
+        }
+    }
+
+    /// Tag the guest expects in front of each tx set
+    fn tag(self) -> u8 {
+        match self {
+            SubTreeProof::Standard => PROOF_TYPE_STANDARD,
+            SubTreeProof::Extended => PROOF_TYPE_EXTENDED,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+pub struct VerifyArgs {
+    /// Receipt file, defaults to receipt.bin or receipt.bin.gz in the state directory
+    pub(crate) receipt: Option<String>,
+
+    /// Journal written by commit --emit-journal, checked against the receipt's
+    #[arg(long)]
+    journal: Option<String>,
+
+    /// Network the receipt must have been proven for
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Batch size limit the receipt must have been proven with
+    #[arg(long, default_value_t = DEFAULT_MAX_ENTRIES)]
+    max_entries: u32,
+
+    /// Block height the receipt must have been proven at, any if omitted
+    #[arg(long)]
+    height: Option<u32>,
+
+    #[command(flatten)]
+    dirs: DirArgs,
+}
+
+type TXSet = Vec<u8>;
+
+/// Guest input spooled to `zkinput.bin` in the state directory while it's
+/// prepared, so subtree proofs are never all held in memory at once. The
+/// file holds the words `ExecutorEnv::write` would produce for the network,
+/// the max entries per tx set and the block height followed by the tx sets as a `Vec<Vec<u8>>`, risc0's serde writes every
+/// byte as a word, and is handed to the prover as stdin. It's removed once
+/// the payload is dropped.
+struct ZKPayload {
+    path: PathBuf,
+    out: Option<io::BufWriter<fs::File>>,
+    tx_sets: u32,
+    /// Running receipt cache key, see `receipt_cache_path`
+    hasher: Sha256,
+}
+
+/// Offset of the tx set count, after the network, max entries and height words
+const ZK_INPUT_COUNT_OFFSET: u64 = 12;
+
+impl ZKPayload {
+    fn create(dirs: &DirArgs, network: Network, max_entries: u32, height: u32) -> Result<Self> {
+        let path = dirs.state_dir()?.join(ZK_INPUT_FILE);
+        let mut out = io::BufWriter::new(fs::File::create(&path)?);
+        out.write_all(&(network as u32).to_le_bytes())?;
+        out.write_all(&max_entries.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // tx set count, filled in by `finish`
+        out.write_all(&0u32.to_le_bytes())?;
+
+        let mut hasher = Sha256::new();
+        for word in SUBSPACER_ID {
+            hasher.update(word.to_le_bytes());
+        }
+        hasher.update([network as u8]);
+        hasher.update(max_entries.to_le_bytes());
+        hasher.update(height.to_le_bytes());
+        Ok(ZKPayload { path, out: Some(out), tx_sets: 0, hasher })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tx_sets == 0
+    }
+
+    /// Appends a tx set laid out like `encode_tx_set`, encoding the subtree
+    /// straight into the file
+    fn push(&mut self, proof_type: u8, root: &Hash, subtree: &SubTree<Sha256Hasher>, tx: &[u8])
+        -> Result<()> {
+        let len = encoded_tx_set_len(subtree, tx).map_err(|e| {
+            RegistryError::Proving(format!("could not encode subtree: {}", e))
+        })?;
+        self.hasher.update((len as u64).to_le_bytes());
+
+        let out = self.out.as_mut().expect("payload is still being written");
+        out.write_all(&(len as u32).to_le_bytes())?;
+        let mut writer = WordWriter { out, hasher: &mut self.hasher, error: None };
+        let result = encode_tx_set_into(&mut writer, proof_type, root, subtree, tx);
+        if let Some(e) = writer.error {
+            return Err(RegistryError::Io(e));
+        }
+        result.map_err(|e| {
+            RegistryError::Proving(format!("could not encode subtree: {}", e))
+        })?;
+        self.tx_sets += 1;
+        Ok(())
+    }
+
+    /// Fills in the tx set count, the payload can't be extended afterwards
+    fn finish(&mut self) -> Result<()> {
+        if let Some(out) = self.out.take() {
+            let mut file = out.into_inner().map_err(|e| e.into_error())?;
+            file.seek(io::SeekFrom::Start(ZK_INPUT_COUNT_OFFSET))?;
+            file.write_all(&self.tx_sets.to_le_bytes())?;
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the tx sets back, for checking them natively
+    fn tx_sets(&self) -> Result<Vec<Vec<u8>>> {
+        let mut input = io::BufReader::new(fs::File::open(&self.path)?);
+        let mut word = [0u8; 4];
+        let mut read_word = |input: &mut io::BufReader<fs::File>| -> Result<u32> {
+            input.read_exact(&mut word)?;
+            Ok(u32::from_le_bytes(word))
+        };
+
+        input.seek(io::SeekFrom::Start(ZK_INPUT_COUNT_OFFSET))?;
+        let count = read_word(&mut input)?;
+        let mut tx_sets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let len = read_word(&mut input)?;
+            let mut tx_set = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                tx_set.push(read_word(&mut input)? as u8);
+            }
+            tx_sets.push(tx_set);
+        }
+        Ok(tx_sets)
+    }
+
+    fn cache_key(&self) -> String {
+        hex::encode(self.hasher.clone().finalize())
+    }
+}
+
+impl Drop for ZKPayload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes every byte as a little endian word and feeds it to the cache key
+struct WordWriter<'a> {
+    out: &'a mut io::BufWriter<fs::File>,
+    hasher: &'a mut Sha256,
+    /// Io errors are kept here, bincode only takes static messages
+    error: Option<io::Error>,
+}
+
+impl bincode::enc::write::Writer for WordWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), bincode::error::EncodeError> {
+        self.hasher.update(bytes);
+        for byte in bytes {
+            if let Err(e) = self.out.write_all(&(*byte as u32).to_le_bytes()) {
+                self.error = Some(e);
+                return Err(bincode::error::EncodeError::Other("could not write guest input"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn prepare_zk_input(dirs: &DirArgs, pool: &mut DatabasePool, args: &ProveOptions,
+                    committed: &CommitState)
+    -> Result<(ZKPayload, HashMap<String, TXSet>)> {
+    let (network, proof) = (args.network, args.proof_type);
+    // sorted so the same staged changes always give the same input and cache key
+    let builders : BTreeMap<String, TransactionBuilder> = load_builders(dirs)?.into_iter().collect();
+    let mut payload = ZKPayload::create(dirs, network, args.max_entries, args.height)?;
+    let mut tx_set : HashMap<String, TXSet> = HashMap::with_capacity(builders.len());
+
+    for (space, mut builder) in builders {
+        if committed.contains_key(&space) {
+            // already applied by an interrupted commit
+            continue;
+        }
+        if builder.network() != Some(network) {
+            return Err(RegistryError::Staging(
+                format!("staged changes for @{} are not for {}", space, network)));
+        }
+        if builder.transactions.is_empty() {
+            // nothing would change, don't spend a proof on it
+            continue;
+        }
+        let exists = pool.get(&space)?.is_some();
+
+        if let Some(db) = pool.get(&space)?.filter(|_| args.skip_existing) {
+            let mut snapshot = db.begin_read()?;
+            let mut kept = Vec::with_capacity(builder.transactions.len());
+            for entry in builder.transactions.drain(..) {
+                if entry.witness.is_empty() && snapshot.get(entry.sort_key().as_bytes())?.is_some() {
+                    println!("Skipping {}@{}, already registered", entry.name, space);
+                    continue;
+                }
+                kept.push(entry);
+            }
+            builder.transactions = kept;
+            if builder.transactions.is_empty() {
+                continue;
+            }
+        }
+
+        let (_, updates) = builder_stats(&builder);
+        if !exists && updates == 0 {
+            // these go unproven, owners that aren't valid keys must still be refused
+            for entry in &builder.transactions {
+                entry.verify(&space, network, None).map_err(|e| {
+                    RegistryError::Validation(format!("@{} rejected: {}", space, e))
+                })?;
+            }
+        }
+        let raw = builder.build(space.as_str()).map_err(|e| {
+            RegistryError::Staging(format!("could not build tx set: {}", e))
+        })?;
+        let raw = tx_set.entry(space.clone()).or_insert_with(|| {
+            raw
+        });
+
+        let (root, subtree) = if exists {
+            space_subtree(pool.get(&space)?, raw.as_slice(), proof.proof_type())?
+        } else if updates == 0 {
+            // registrations into a new space need no proof of the initial state
+            continue;
+        } else {
+            // updates always go through the guest so their witnesses are checked,
+            // even against a space with no database yet
+            space_subtree(None, raw.as_slice(), proof.proof_type())?
+        };
+
+        // the guest checks the subtree against the committed root
+        payload.push(proof.tag(), &root, &subtree, raw.as_slice())?;
+    }
+
+    payload.finish()?;
+    Ok((payload, tx_set))
+}
+
+/// Committed root of a space and a subtree proving the keys touched by `raw`,
+/// a space without a database starts from the empty tree
+fn space_subtree(db: Option<&Database<Sha256Hasher>>, raw: &[u8], proof_type: ProofType)
+    -> Result<(Hash, SubTree<Sha256Hasher>)> {
+    let db = match db {
+        Some(db) => db,
+        None => {
+            let subtree = SubTree::<Sha256Hasher>::empty();
+            return Ok((subtree.root()?, subtree));
+        }
+    };
+
+    // create subtree
+    let reader = TransactionReader(raw);
+    let keys : Vec<Hash> = reader.iter().map(|t| t.subspace_hash().0).collect();
+
+    let mut snapshot = db.begin_read()?;
+    let subtree = snapshot.prove(&keys, proof_type).map_err(|e| {
+        RegistryError::Proving(format!("could not generate subtree: {}", e))
+    })?;
+    Ok((snapshot.root()?, subtree))
+}
+
+/// Runs the guest logic natively over a space's staged changes to preview the
+/// root a proof would commit to, without proving
+fn project_final_root(space: &str, builder: TransactionBuilder, db: Option<&Database<Sha256Hasher>>, height: u32)
+    -> Result<Hash> {
+    let network = builder.network().ok_or_else(|| {
+        RegistryError::Staging(format!("staged changes for @{} have an unknown network", space))
+    })?;
+    let raw = builder.build(space).map_err(|e| {
+        RegistryError::Staging(format!("could not build tx set: {}", e))
+    })?;
+    let (root, subtree) = space_subtree(db, raw.as_slice(), ProofType::Standard)?;
+    let input = encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, raw.as_slice()).map_err(|e| {
+        RegistryError::Proving(format!("could not encode subtree: {}", e))
+    })?;
+    let commitment = handle_tx_set(network as u8, DEFAULT_MAX_ENTRIES, height, input).map_err(|e| {
+        RegistryError::Validation(format!("@{} would be rejected: {}", space, e))
+    })?;
+    Ok(commitment.final_root)
+}
+
+/// Prints the current and projected root of every staged space
+fn dry_run(dirs: &DirArgs, height: u32) -> Result<()> {
+    let builders : BTreeMap<String, TransactionBuilder> = load_builders(dirs)?.into_iter().collect();
+    let names = load_names(dirs)?;
+    let mut pool = DatabasePool::new(dirs)?;
+
+    println!("Projected Root Changes");
+    println!("-------------------------------------");
+    for (space, builder) in builders {
+        let db = pool.get(&space)?;
+        let initial_root = match db {
+            Some(db) => db.begin_read()?.root()?,
+            None => SubTree::<Sha256Hasher>::empty().root()?,
+        };
+        let final_root = project_final_root(space.as_str(), builder, db, height)?;
+
+        println!("\tID: {}", display_hash(&names, &Sha256::digest(space.as_bytes())));
+        println!("\t- Initial: {}", hex::encode(initial_root));
+        println!("\t- Final: {}", hex::encode(final_root));
+        println!();
+    }
+    Ok(())
+}
+
+/// Proves, retrying failures that may go away on their own up to `retries`
+/// times with exponential backoff. A guest that fails on the input fails
+/// straight away, as does a timeout since the abandoned proof is still running.
+fn run_prover(zk_input: &ZKPayload, kind: Option<ProverKind>, timeout: Option<Duration>,
+              retries: u32) -> Result<Receipt> {
+    if let Some(kind) = kind {
+        kind.check()?;
+    }
+
+    let mut attempt = 0;
+    loop {
+        if retries > 0 {
+            println!("- Attempt {} of {}", attempt + 1, retries + 1);
+        }
+        let e = match prove_once(zk_input, kind, timeout)? {
+            Ok(receipt) => return Ok(receipt),
+            Err(e) => e,
+        };
+        if attempt == retries || !is_retryable(zk_input, &e) {
+            return Err(RegistryError::Proving(format!("could not prove elf: {}", e)));
+        }
+        let backoff = RETRY_BACKOFF * 2u32.pow(attempt.min(MAX_BACKOFF_DOUBLINGS));
+        println!("- Proving failed: {}", e);
+        println!("- Retrying in {:?}", backoff);
+        thread::sleep(backoff);
+        attempt += 1;
+    }
+}
+
+/// Whether proving `zk_input` again may succeed after the prover failed
+/// with `e`. The input is executed without proving: a guest that fails there
+/// fails the same way on every attempt, one that runs to the end means the
+/// prover itself failed. risc0 has no error type for a guest panic, so the
+/// error text is only checked if the executor can't be set up.
+fn is_retryable(zk_input: &ZKPayload, e: &str) -> bool {
+    let executed = fs::File::open(&zk_input.path).ok().and_then(|input| {
+        let env = ExecutorEnv::builder().stdin(io::BufReader::new(input)).build().ok()?;
+        Some(default_executor().execute(env, SUBSPACER_ELF).is_ok())
+    });
+    executed.unwrap_or_else(|| !e.contains(GUEST_PANIC))
+}
+
+/// Proves on a worker thread so a `timeout` can give up on a runaway proof.
+/// The prover can't be cancelled, on timeout the thread is abandoned and
+/// keeps running until the process exits. Errors from the prover itself are
+/// returned in the inner result so the caller can decide to retry.
+fn prove_once(zk_input: &ZKPayload, kind: Option<ProverKind>, timeout: Option<Duration>)
+    -> Result<Result<Receipt, String>> {
+    let input = io::BufReader::new(fs::File::open(&zk_input.path)?);
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let env = ExecutorEnv::builder()
+            .stdin(input)
+            .build().unwrap();
+        let prover = kind.map(ProverKind::prover).unwrap_or_else(default_prover);
+
+        println!("Proving Started ...");
+        println!("-------------------------------------");
+        println!("- Using Prover: {}", prover.get_name());
+
+        // Produce a receipt by proving the specified ELF binary.
+        let result = prover.prove(env, SUBSPACER_ELF).map_err(|e| e.to_string());
+        // the receiver is gone if we timed out
+        let _ = sender.send(result);
+    });
+
+    let start = std::time::Instant::now();
+    let result = match timeout {
+        Some(timeout) => receiver.recv_timeout(timeout).map_err(|_| {
+            RegistryError::Proving(format!("proving did not finish within {:?}", timeout))
+        })?,
+        None => receiver.recv().map_err(|_| {
+            RegistryError::Proving(String::from("prover thread exited unexpectedly"))
+        })?,
+    };
+    if result.is_ok() {
+        println!("- Took: {:?}", start.elapsed());
+    }
+    Ok(result)
+}
+
+/// Receipts are cached by a hash of the image ID and the full guest input,
+/// so a new guest build never reuses a stale receipt
+fn receipt_cache_path(dirs: &DirArgs, zk_input: &ZKPayload) -> Result<PathBuf> {
+    let dir = dirs.state_dir()?.join(CACHE_DIR);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.bin", zk_input.cache_key())))
+}
+
+fn cached_receipt(path: &Path) -> Option<Receipt> {
+    if !path.exists() {
+        return None;
+    }
+    let receipt = read_receipt(path).ok()?;
+    receipt.verify(SUBSPACER_ID).ok()?;
+    Some(receipt)
+}
+
+fn prove(dirs: &DirArgs, args: &ProveOptions, no_proof: bool, pool: &mut DatabasePool,
+         committed: &CommitState) -> Result<(Vec<Commitment>, HashMap<String, TXSet>)> {
+    // Initialize tracing. In order to view logs, run `RUST_LOG=info cargo run`
+    // A long running caller may prove more than once, only the first sets it up
+    let _ = env_logger::try_init();
+    let network = args.network;
+    let (zk_input, tx_set) = prepare_zk_input(dirs, pool, args, committed)?;
+    if zk_input.is_empty() {
+        return Ok((Vec::new(), tx_set));
+    }
+
+    if no_proof {
+        // same checks as the guest, just without a receipt to show for it
+        let output = program::guest::run(network as u8, args.max_entries, args.height, zk_input.tx_sets()?).map_err(|e| {
+            RegistryError::Validation(format!("tx set rejected: {}", e))
+        })?;
+        println!("- WARNING: Not proven (--no-proof), checked natively\n");
+        return Ok((output, tx_set));
+    }
+
+    // Reuse a previous receipt if the exact same input was proven before
+    let cache_path = receipt_cache_path(dirs, &zk_input)?;
+    let cached = cached_receipt(&cache_path);
+    let from_cache = cached.is_some();
+    let receipt = match cached {
+        Some(receipt) => {
+            println!("- Using cached receipt: {}", cache_path.display());
+            receipt
+        }
+        None => {
+            let receipt = run_prover(&zk_input, args.prover,
+                                     args.timeout.map(Duration::from_secs), args.retries)?;
+            write_receipt(&cache_path, &receipt)?;
+            receipt
+        }
+    };
+
+    if from_cache {
+        println!("- Receipt Verified\n");
+    } else if args.no_verify_receipt {
+        println!("- WARNING: Receipt NOT verified (--no-verify-receipt)\n");
+    } else {
+        receipt.verify(SUBSPACER_ID).map_err(|e| {
+            RegistryError::Proving(format!("could not verify receipt: {}", e))
+        })?;
+
+        println!("- Receipt Verified\n");
+    }
+
+    let output : Vec<Commitment> = receipt.journal.decode().map_err(|e| {
+        RegistryError::Proving(format!("could not decode receipt: {}", e))
+    })?;
+    check_journal(&output, network, args.max_entries, Some(args.height))?;
+
+    // save receipt to output arg
+    write_receipt(&saved_receipt_path(dirs, args)?, &receipt)?;
+
+    Ok((output, tx_set))
+}
+
+/// Where `prove` saves the receipt of the last proof
+fn saved_receipt_path(dirs: &DirArgs, args: &ProveOptions) -> Result<PathBuf> {
+    let filename = if args.compress { RECEIPT_FILE_GZ } else { RECEIPT_FILE };
+    Ok(dirs.state_dir()?.join(filename))
+}
+
+/// Receipt files start with these bytes, followed by the format version and the image ID
+const RECEIPT_MAGIC: &[u8; 4] = b"SUBR";
+const RECEIPT_FORMAT_VERSION: u8 = 1;
+const RECEIPT_HEADER_SIZE: usize = RECEIPT_MAGIC.len() + 1 + 32;
+
+/// Journal files start with these bytes, followed by the format version and
+/// the image ID like receipts, then the journal exactly as in the receipt
+const JOURNAL_MAGIC: &[u8; 4] = b"SUBJ";
+const JOURNAL_FORMAT_VERSION: u8 = 1;
+
+fn receipt_header() -> [u8; RECEIPT_HEADER_SIZE] {
+    image_header(RECEIPT_MAGIC, RECEIPT_FORMAT_VERSION)
+}
+
+fn image_header(magic: &[u8; 4], version: u8) -> [u8; RECEIPT_HEADER_SIZE] {
+    let mut header = [0u8; RECEIPT_HEADER_SIZE];
+    header[..magic.len()].copy_from_slice(magic);
+    header[magic.len()] = version;
+    for (i, word) in SUBSPACER_ID.iter().enumerate() {
+        let offset = magic.len() + 1 + i * 4;
+        header[offset..offset + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    header
+}
+
+/// Writes the journal of `receipt` on its own, for consumers that only
+/// need the commitments. It decodes as a `Vec<Commitment>` with risc0's serde.
+fn write_journal(path: &Path, receipt: &Receipt) -> Result<()> {
+    let mut raw_journal = image_header(JOURNAL_MAGIC, JOURNAL_FORMAT_VERSION).to_vec();
+    raw_journal.extend_from_slice(&receipt.journal.bytes);
+    fs::write(path, raw_journal)?;
+    Ok(())
+}
+
+/// Reads the journal bytes of a file written by `write_journal`
+fn read_journal(path: &Path) -> Result<Vec<u8>> {
+    let raw_journal = fs::read(path)?;
+    if raw_journal.len() < RECEIPT_HEADER_SIZE || !raw_journal.starts_with(JOURNAL_MAGIC) {
+        return Err(RegistryError::Validation(format!("{} is not a journal file", path.display())));
+    }
+    let (header, journal) = raw_journal.split_at(RECEIPT_HEADER_SIZE);
+    if header != image_header(JOURNAL_MAGIC, JOURNAL_FORMAT_VERSION) {
+        return Err(RegistryError::Validation(
+            String::from("journal has a different format version or guest image")));
+    }
+    Ok(journal.to_vec())
+}
+
+/// Writes a header followed by the bincode encoded receipt, gzipped if the path ends in `.gz`
+fn write_receipt(path: &Path, receipt: &Receipt) -> Result<()> {
+    let mut raw_receipt = receipt_header().to_vec();
+    let encoded = bincode::serde::encode_to_vec(receipt, bincode::config::standard())
+        .map_err(|e| {
+        RegistryError::Proving(format!("could not serialize receipt: {}", e))
+    })?;
+    raw_receipt.extend_from_slice(&encoded);
+
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        let mut encoder = GzEncoder::new(fs::File::create(path)?, Compression::default());
+        encoder.write_all(&raw_receipt)?;
+        encoder.finish()?;
+    } else {
+        fs::write(path, raw_receipt)?;
+    }
+    Ok(())
+}
+
+/// Reads a receipt written by `write_receipt`, checking its header first so
+/// incompatible files fail with a clear message instead of a decode error
+fn read_receipt(path: &Path) -> Result<Receipt> {
+    let mut raw_receipt = Vec::new();
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        GzDecoder::new(fs::File::open(path)?).read_to_end(&mut raw_receipt)?;
+    } else {
+        raw_receipt = fs::read(path)?;
+    }
+
+    if raw_receipt.len() < RECEIPT_HEADER_SIZE || !raw_receipt.starts_with(RECEIPT_MAGIC) {
+        return Err(RegistryError::Validation(
+            format!("{} is not a receipt file (or was written before receipts had a header)", path.display())));
+    }
+    let (header, raw_receipt) = raw_receipt.split_at(RECEIPT_HEADER_SIZE);
+    let version = header[RECEIPT_MAGIC.len()];
+    if version != RECEIPT_FORMAT_VERSION {
+        return Err(RegistryError::Validation(
+            format!("receipt format version mismatch: file has {}, expected {}", version, RECEIPT_FORMAT_VERSION)));
+    }
+    if header != receipt_header() {
+        return Err(RegistryError::Validation(
+            String::from("receipt was produced by a different guest image")));
+    }
+
+    let (receipt, _) = bincode::serde::decode_from_slice(raw_receipt, bincode::config::standard())
+        .map_err(|e| {
+        RegistryError::Validation(format!("could not deserialize receipt: {}", e))
+    })?;
+    Ok(receipt)
+}
+
+/// A receipt only proves the guest accepted its input for the network,
+/// batch size limit and height in its journal, those have to be the ones we
+/// expect. Any height is fine if none is expected.
+fn check_journal(output: &[Commitment], network: Network, max_entries: u32, height: Option<u32>) -> Result<()> {
+    for commitment in output {
+        if let Some(height) = height.filter(|height| *height != commitment.height) {
+            return Err(RegistryError::Validation(
+                format!("receipt was proven at height {}, expected {}", commitment.height, height)));
+        }
+        if commitment.network != network as u8 {
+            return Err(RegistryError::Validation(
+                format!("receipt was proven for network {}, expected {}", commitment.network, network)));
+        }
+        if commitment.max_entries != max_entries {
+            return Err(RegistryError::Validation(
+                format!("receipt was proven with max entries {}, expected {}", commitment.max_entries, max_entries)));
+        }
+    }
+    Ok(())
+}
+
+fn print_journal(output: &[Commitment], names: &NameIndex) {
+    println!("Journal Output");
+    println!("-------------------------------------");
+    println!("Total Spaces: {}\n", output.len());
+    for commitment in output.iter() {
+        println!("\tID: {}", display_hash(names, &commitment.space));
+        println!("\tMerkle Root Changes: ");
+        println!("\t- Initial: {}", hex::encode(commitment.initial_root));
+        println!("\t- Final: {}", hex::encode(commitment.final_root));
+        println!("\n\n")
+    }
+}
+
+/// The given receipt, or the one `commit` and `prove` write to the state directory
+fn receipt_path(dirs: &DirArgs, receipt: Option<String>) -> Result<PathBuf> {
+    Ok(match receipt {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let wd = dirs.state_dir()?;
+            let path = wd.join(RECEIPT_FILE);
+            if path.exists() { path } else { wd.join(RECEIPT_FILE_GZ) }
+        }
+    })
+}
+
+pub fn verify(args: VerifyArgs) -> Result<()> {
+    let path = receipt_path(&args.dirs, args.receipt)?;
+
+    let receipt = read_receipt(&path)?;
+    receipt.verify(SUBSPACER_ID).map_err(|e| {
+        RegistryError::Validation(format!("could not verify receipt: {}", e))
+    })?;
+    println!("- Receipt Verified\n");
+
+    if let Some(journal) = args.journal {
+        if read_journal(Path::new(&journal))? != receipt.journal.bytes {
+            return Err(RegistryError::Validation(
+                format!("{} does not match the journal of {}", journal, path.display())));
+        }
+        println!("- Journal Matches\n");
+    }
+
+    let output : Vec<Commitment> = receipt.journal.decode().map_err(|e| {
+        RegistryError::Validation(format!("could not decode receipt: {}", e))
+    })?;
+    check_journal(&output, args.network, args.max_entries, args.height)?;
+    print_journal(&output, &load_names(&args.dirs)?);
+    Ok(())
+}
+
+/// Maps spaces applied during a commit to their hex encoded root after applying
+type CommitState = HashMap<String, String>;
+
+fn load_commit_state(dirs: &DirArgs) -> Result<CommitState> {
+    let path = dirs.state_dir()?.join(COMMIT_STATE_FILE);
+    if !path.exists() {
+        return Ok(CommitState::new());
+    }
+    let raw = fs::read(path)?;
+    let state = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
+        RegistryError::Staging(format!("could not parse {}", COMMIT_STATE_FILE))
+    })?;
+    Ok(state)
+}
+
+fn save_commit_state(state: &CommitState, dirs: &DirArgs) -> Result<()> {
+    let str = serde_json::to_string_pretty(state).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize commit state"))
+    })?;
+    fs::write(dirs.state_dir()?.join(COMMIT_STATE_FILE), str)?;
+    Ok(())
+}
+
+/// Unix time each tx set in `committed.log` was last committed at, keyed by
+/// the hex sha256 of the tx set. Each line is `<hash> <unix time> <space>`,
+/// followed by the space's initial and final root in lines written since
+/// roots were recorded.
+fn load_commit_log(dirs: &DirArgs) -> Result<HashMap<String, u64>> {
+    let path = dirs.state_dir()?.join(COMMIT_LOG_FILE);
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let mut log = HashMap::new();
+    for line in fs::read_to_string(path)?.lines() {
+        let mut fields = line.split_whitespace();
+        let entry = fields.next().zip(fields.next().and_then(|ts| ts.parse().ok()));
+        let (hash, ts) = entry.ok_or_else(|| {
+            RegistryError::Staging(format!("could not parse {}", COMMIT_LOG_FILE))
+        })?;
+        log.insert(String::from(hash), ts);
+    }
+    Ok(log)
+}
+
+fn append_commit_log(dirs: &DirArgs, space: &str, raw: &[u8], initial_root: &str, final_root: &str)
+    -> Result<()> {
+    let ts = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut log = fs::OpenOptions::new().create(true).append(true)
+        .open(dirs.state_dir()?.join(COMMIT_LOG_FILE))?;
+    writeln!(log, "{} {} {} {} {}", hex::encode(Sha256::digest(raw)), ts, space, initial_root, final_root)?;
+    Ok(())
+}
+
+/// Refuses staged tx sets identical to ones committed before, such as an
+/// `uncommitted.json` restored from a backup. Spaces applied by an
+/// interrupted commit are skipped, they're resumed rather than replayed.
+fn check_replay(dirs: &DirArgs, committed: &CommitState) -> Result<()> {
+    let log = load_commit_log(dirs)?;
+    if log.is_empty() {
+        return Ok(());
+    }
+    for (space, builder) in load_builders(dirs)? {
+        if committed.contains_key(&space) || builder.transactions.is_empty() {
+            continue;
+        }
+        let raw = builder.build(space.as_str()).map_err(|e| {
+            RegistryError::Staging(format!("could not build tx set: {}", e))
+        })?;
+        if let Some(ts) = log.get(&hex::encode(Sha256::digest(&raw))) {
+            return Err(RegistryError::Validation(
+                format!("staged changes for @{} were already committed at {} (unix time), \
+                         pass --allow-replay to commit them again", space, ts)));
+        }
+    }
+    Ok(())
+}
+
+/// Name to owner changes applied by a tx set, for the commit manifest
+fn manifest_changes(raw: &[u8], names: &NameIndex) -> Vec<serde_json::Value> {
+    TransactionReader(raw).iter().map(|t| {
+        let subspace = hex::encode(t.subspace_hash());
+        serde_json::json!({
+            "name": names.get(&subspace),
+            "subspace": subspace,
+            "owner": hex::encode(t.owner()),
+        })
+    }).collect()
+}
+
+/// Records what a commit applied in `commit-manifest.json` next to the
+/// receipt, for operators to archive or hand to other systems. Written
+/// fresh on every commit.
+fn write_commit_manifest(dirs: &DirArgs, spaces: serde_json::Map<String, serde_json::Value>,
+                         receipt: Option<String>) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let image_id : Vec<u8> = SUBSPACER_ID.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let manifest = serde_json::json!({
+        "timestamp": timestamp,
+        "image_id": hex::encode(image_id),
+        "receipt": receipt,
+        "spaces": spaces,
+    });
+
+    let str = serde_json::to_string_pretty(&manifest).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize commit manifest"))
+    })?;
+    let path = dirs.state_dir()?.join(COMMIT_MANIFEST_FILE);
+    fs::write(&path, str)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Checks a tx set can be written to a space and returns the root the space
+/// ends up at. The database must still be at the root the proof started
+/// from, and running the tx set natively must reproduce the proven
+/// commitment, so a tx set changed after proving is never written. A tx set
+/// no proof covers may only register names in a space without a database,
+/// it's run natively so the guest's checks still apply.
+fn expected_final_root(pool: &mut DatabasePool, space: &str, raw: &[u8], network: Network,
+                       max_entries: u32, height: u32, commitment: Option<&Commitment>) -> Result<Hash> {
+    match (commitment, pool.get(space)?.map(LocalStore::new)) {
+        (Some(commitment), Some(mut store)) => {
+            let root = store.read_root()?;
+            if root != commitment.initial_root {
+                return Err(RegistryError::Validation(
+                    format!("@{} is at root {}, the proof starts from {}",
+                            space, hex::encode(root), hex::encode(commitment.initial_root))));
+            }
+        }
+        (Some(commitment), None) => {
+            if commitment.initial_root != SubTree::<Sha256Hasher>::empty().root()? {
+                return Err(RegistryError::Validation(format!("no database for @{}", space)));
+            }
+        }
+        (None, Some(_)) => {
+            return Err(RegistryError::Validation(format!("no proof covers @{}", space)));
+        }
+        (None, None) => {
+            if tx_reader(space, raw)?.updates().next().is_some() {
+                return Err(RegistryError::Validation(
+                    format!("no proof covers the updates to @{}", space)));
+            }
+        }
+    }
+
+    let native = native_commitment(pool, space, raw, network, max_entries, height)?;
+    match commitment {
+        Some(commitment) if native != *commitment => Err(RegistryError::Validation(
+            format!("tx set for @{} does not produce the proven root {}",
+                    space, hex::encode(commitment.final_root)))),
+        _ => Ok(native.final_root),
+    }
+}
+
+/// Runs a tx set natively against the space's database, the same checks
+/// the guest makes, for the network the caller expects rather than the one
+/// the tx set claims
+fn native_commitment(pool: &mut DatabasePool, space: &str, raw: &[u8], network: Network, max_entries: u32,
+                     height: u32) -> Result<Commitment> {
+    if tx_reader(space, raw)?.network() != network as u8 {
+        return Err(RegistryError::Validation(
+            format!("tx set for @{} is not for {}", space, network)));
+    }
+    let (root, subtree) = space_subtree(pool.get(space)?, raw, ProofType::Standard)?;
+    let input = encode_tx_set(PROOF_TYPE_STANDARD, &root, &subtree, raw).map_err(|e| {
+        RegistryError::Proving(format!("could not encode subtree: {}", e))
+    })?;
+    handle_tx_set(network as u8, max_entries, height, input).map_err(|e| {
+        RegistryError::Validation(format!("tx set for @{} rejected: {}", space, e))
+    })
+}
+
+fn tx_reader<'a>(space: &str, raw: &'a [u8]) -> Result<TransactionReader<'a>> {
+    TransactionReader::try_new(raw).map_err(|_e| {
+        RegistryError::Validation(format!("tx set for @{} is truncated", space))
+    })
+}
+
+/// Writes the owners set by a tx set to a space's store and checks the
+/// store ends up at `final_root`, the root the guest computed for the same
+/// changes, so the host write can't silently diverge from what was proven
+fn write_tx_set(store: &mut dyn RegistryStore, space: &str, raw: &[u8], final_root: &Hash) -> Result<()> {
+    let reader = TransactionReader(raw);
+
+    for t in reader.iter() {
+        let memo = parse_witness(t.witness()).map(|w| w.memo).unwrap_or(&[]);
+        store.insert(t.subspace_hash().0, owner_value(t.owner(), memo))?;
+    }
+    store.commit()?;
+
+    let root = store.read_root()?;
+    if root != *final_root {
+        return Err(RegistryError::Validation(
+            format!("@{} is at root {} after writing, the proof ends at {}",
+                    space, hex::encode(root), hex::encode(final_root))));
+    }
+    Ok(())
+}
+
+fn db_root(store: &mut dyn RegistryStore) -> Result<String> {
+    Ok(hex::encode(store.read_root()?))
+}
+
+fn confirm_commit(dirs: &DirArgs) -> Result<bool> {
+    if !atty::is(Stream::Stdin) {
+        return Err(RegistryError::Validation(
+            String::from("refusing to commit without confirmation (use --yes for non-interactive commits)")));
+    }
+
+    let builders = load_builders(dirs)?;
+    let mut registrations = 0;
+    let mut updates = 0;
+    for builder in builders.values() {
+        let (r, u) = builder_stats(builder);
+        registrations += r;
+        updates += u;
+    }
+
+    println!("About to prove and commit:");
+    println!("Total spaces: {}, Total Registrations: {}, Total Updates: {}",
+             builders.len(), registrations, updates);
+    print!("Continue? [y/N] ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+pub fn commit(args : CommitArgs) -> Result<Outcome> {
+    args.dirs.prepare()?;
+    let _lock = lock_staging(&args.dirs)?;
+    let state_dir = args.dirs.state_dir()?;
+    let uncommitted_path = state_dir.join(STAGING_FILE);
+    if !uncommitted_path.exists() {
+        println!("No changes to prove and commit (use \"registry add\" to add changes)");
+        return Ok(Outcome::NothingToDo);
+    }
+
+    if args.dry_run {
+        dry_run(&args.dirs, args.proving.height)?;
+        return Ok(Outcome::Done);
+    }
+
+    if !args.yes && !confirm_commit(&args.dirs)? {
+        println!("Aborted");
+        return Ok(Outcome::Done);
+    }
+
+    // Spaces applied by a previous commit that was interrupted
+    let mut pool = DatabasePool::new(&args.dirs)?;
+    let mut committed = load_commit_state(&args.dirs)?;
+    for (space, root) in committed.iter() {
+        if db_root(&mut pool.store(space)?)? != *root {
+            return Err(RegistryError::Validation(
+                format!("@{} does not match the root recorded in {}", space, COMMIT_STATE_FILE)));
+        }
+        println!("Skipping @{}, already committed", space);
+    }
+
+    if !args.allow_replay {
+        check_replay(&args.dirs, &committed)?;
+    }
+
+    if !args.no_names {
+        // changes may have been staged without going through `add`
+        record_names(&args.dirs, &load_builders(&args.dirs)?)?;
+    }
+
+    let staged = load_builders(&args.dirs)?;
+    let (output, tx_set) = prove(&args.dirs, &args.proving, args.no_proof, &mut pool, &committed)?;
+    if tx_set.is_empty() && committed.is_empty() {
+        // every staged builder is empty
+        println!("No changes to prove and commit (use \"registry add\" to add changes)");
+        return Ok(Outcome::NothingToDo);
+    }
+
+    print_journal(&output, &load_names(&args.dirs)?);
+
+    println!("Committing changes ...");
+
+    // the databases may have changed while proving
+    let mut final_roots = HashMap::with_capacity(tx_set.len());
+    for (space, raw) in tx_set.iter() {
+        let space_hash : Hash = Sha256::digest(space.as_bytes()).into();
+        let commitment = output.iter().find(|c| c.space == space_hash);
+        final_roots.insert(space.clone(), expected_final_root(&mut pool, space, raw, args.proving.network,
+                                                           args.proving.max_entries, args.proving.height, commitment)?);
+    }
+
+    let names = if args.no_names { NameIndex::new() } else { load_names(&args.dirs)? };
+    let mut manifest = serde_json::Map::new();
+    for (space, raw) in tx_set {
+        let mut store = pool.store(&space)?;
+        let initial_root = db_root(&mut store)?;
+        write_tx_set(&mut store, &space, &raw, &final_roots[&space])?;
+        let final_root = db_root(&mut store)?;
+        append_commit_log(&args.dirs, &space, &raw, &initial_root, &final_root)?;
+        manifest.insert(space.clone(), serde_json::json!({
+            "initial_root": initial_root,
+            "final_root": final_root,
+            "changes": manifest_changes(&raw, &names),
+        }));
+        committed.insert(space.clone(), final_root);
+        save_commit_state(&committed, &args.dirs)?;
+    }
+
+    // nothing is proven for registrations in new spaces alone
+    let receipt = if args.no_proof || output.is_empty() {
+        None
+    } else {
+        Some(saved_receipt_path(&args.dirs, &args.proving)?)
+    };
+    if let Some(journal) = &args.emit_journal {
+        match &receipt {
+            Some(receipt) => {
+                write_journal(Path::new(journal), &read_receipt(receipt)?)?;
+                println!("Wrote journal to {}", journal);
+            }
+            None => println!("Nothing was proven, no journal written"),
+        }
+    }
+    write_commit_manifest(&args.dirs, manifest, receipt.map(|path| path.display().to_string()))?;
+    history::record(&args.dirs, "commit", &staged.into_iter().collect(), history::Effect::Committed(&committed),
+                    args.max_history)?;
+
+    // remove uncommitted.json, or keep it around as a record of what was committed
+    if uncommitted_path.exists() {
+        if args.keep_staging {
+            let ts = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+            let kept = state_dir.join(format!("committed-{}.json", ts));
+            fs::rename(uncommitted_path, &kept)?;
+            println!("Kept staged changes in {}", kept.display());
+        } else {
+            fs::remove_file(uncommitted_path)?;
+        }
+    }
+    let state = state_dir.join(COMMIT_STATE_FILE);
+    if state.exists() {
+        fs::remove_file(state)?;
+    }
+
+    println!("Done!");
+    Ok(Outcome::Done)
+}
+
+/// Proves staged changes and writes the receipt along with the tx sets it
+/// covers, so `apply` can commit them elsewhere. Staged changes and the
+/// databases are left alone.
+pub fn prove_only(args: ProveArgs) -> Result<Outcome> {
+    args.dirs.prepare()?;
+    let _lock = lock_staging(&args.dirs)?;
+    let committed = load_commit_state(&args.dirs)?;
+    let mut pool = DatabasePool::new(&args.dirs)?;
+    let (output, tx_set) = prove(&args.dirs, &args.proving, false, &mut pool, &committed)?;
+    if tx_set.is_empty() {
+        println!("No changes to prove (use \"registry add\" to add changes)");
+        return Ok(Outcome::NothingToDo);
+    }
+    if output.is_empty() {
+        return Err(RegistryError::Staging(
+            String::from("staged changes only register names in new spaces, nothing to prove (use \"registry commit\")")));
+    }
+
+    let encoded : BTreeMap<&String, String> = tx_set.iter().map(|(space, raw)| (space, hex::encode(raw))).collect();
+    let str = serde_json::to_string_pretty(&encoded).map_err(|_e| {
+        RegistryError::Staging(String::from("unable to serialize tx sets"))
+    })?;
+    let path = args.dirs.state_dir()?.join(TX_SETS_FILE);
+    fs::write(&path, str)?;
+
+    print_journal(&output, &load_names(&args.dirs)?);
+    println!("Wrote tx sets to {}", path.display());
+    println!("  (use \"registry apply\" to commit them)");
+    Ok(Outcome::Done)
+}
+
+/// Runs `prove_only` on tokio's blocking thread pool, so an async caller
+/// stays responsive for the minutes a proof takes
+#[cfg(feature = "async")]
+pub async fn prove_async(args: ProveArgs) -> Result<Outcome> {
+    tokio::task::spawn_blocking(move || prove_only(args))
+        .await
+        .map_err(|e| RegistryError::Proving(format!("proving task failed: {}", e)))?
+}
+
+/// Commits tx sets written by `prove` once the receipt verifies, every
+/// database is still at the root its proof started from and every tx set
+/// reproduces its proven commitment. Spaces without a database may take
+/// registrations without a proof, same as `commit`.
+pub fn apply(args: ApplyArgs) -> Result<()> {
+    args.dirs.prepare()?;
+    let receipt = read_receipt(&receipt_path(&args.dirs, args.receipt)?)?;
+    receipt.verify(SUBSPACER_ID).map_err(|e| {
+        RegistryError::Validation(format!("could not verify receipt: {}", e))
+    })?;
+    let output : Vec<Commitment> = receipt.journal.decode().map_err(|e| {
+        RegistryError::Validation(format!("could not decode receipt: {}", e))
+    })?;
+    check_journal(&output, args.network, args.max_entries, args.height)?;
+
+    let path = match args.tx_sets {
+        Some(path) => PathBuf::from(path),
+        None => args.dirs.state_dir()?.join(TX_SETS_FILE),
+    };
+    let raw = fs::read(&path)?;
+    let encoded : BTreeMap<String, String> = serde_json::from_slice(raw.as_slice()).map_err(|_e| {
+        RegistryError::Validation(format!("could not parse {}", path.display()))
+    })?;
+
+    // check everything before writing anything
+    let mut pool = DatabasePool::new(&args.dirs)?;
+    let mut tx_sets = Vec::with_capacity(encoded.len());
+    let mut covered = 0;
+    for (space, raw) in encoded {
+        let raw = hex::decode(&raw).map_err(|_e| {
+            RegistryError::Validation(format!("invalid tx set for @{}", space))
+        })?;
+        let space_hash : Hash = Sha256::digest(space.as_bytes()).into();
+        if TransactionReader::try_new(raw.as_slice()).map(|r| r.space_hash().0 != space_hash).unwrap_or(true) {
+            return Err(RegistryError::Validation(
+                format!("tx set listed under @{} is for another space", space)));
+        }
+        let commitment = output.iter().find(|c| c.space == space_hash);
+        covered += commitment.is_some() as usize;
+        // a tx set no proof covers only registers names, its height doesn't matter
+        let height = commitment.map_or(args.height.unwrap_or_default(), |c| c.height);
+        let final_root = expected_final_root(&mut pool, &space, &raw, args.network, args.max_entries, height,
+                                             commitment)?;
+        tx_sets.push((space, raw, final_root));
+    }
+    if covered < output.len() {
+        return Err(RegistryError::Validation(
+            String::from("receipt covers spaces missing from the tx sets")));
+    }
+
+    for (space, raw, expected) in tx_sets {
+        let mut store = pool.store(&space)?;
+        let initial_root = db_root(&mut store)?;
+        write_tx_set(&mut store, &space, &raw, &expected)?;
+        let final_root = db_root(&mut store)?;
+        append_commit_log(&args.dirs, &space, &raw, &initial_root, &final_root)?;
+        println!("Applied @{}", space);
+    }
+    println!("Done!");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use crate::{Cli, Options};
+
+    #[test]
+    fn empty_builder_is_nothing_to_do() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged = format!(r#"{{"foo": {{"version": {}, "transactions": []}}}}"#, program::FORMAT_VERSION);
+        fs::write(dir.path().join(STAGING_FILE), staged).unwrap();
+
+        let dir_arg = dir.path().to_str().unwrap();
+        let args = match Options::parse_from(["registry", "commit", "-C", dir_arg, "--yes", "--no-names"]).command {
+            Cli::Commit(args) => args,
+            _ => unreachable!(),
+        };
+        assert!(matches!(commit(args), Ok(Outcome::NothingToDo)));
+        assert!(!dir.path().join(ZK_INPUT_FILE).exists());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn with_network_takes_the_command_line_defaults() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = ProveArgs::with_network(DirArgs { c: Some(dir.path().to_path_buf()), db_dir: None, state_dir: None },
+                                           Network::Testnet);
+        assert_eq!(args.proving.network, Network::Testnet);
+        assert_eq!(args.proving.max_entries, DEFAULT_MAX_ENTRIES);
+        assert!(!args.proving.skip_existing);
+    }
+
+    #[test]
+    fn zk_input_is_deterministic() {
+        let dir = tempfile::tempdir().unwrap();
+        let owner = "11".repeat(program::OWNER_SIZE);
+        let staged = format!(r#"{{"bar": {{"version": {v}, "transactions": [{{"name": "alice", "owner": "{o}"}}]}},
+                                 "foo": {{"version": {v}, "transactions": [{{"name": "bob", "owner": "{o}"}}]}}}}"#,
+                             v = program::FORMAT_VERSION, o = owner);
+        fs::write(dir.path().join(STAGING_FILE), staged).unwrap();
+
+        let dir_arg = dir.path().to_str().unwrap();
+        let args = match Options::parse_from(["registry", "prove", "-C", dir_arg]).command {
+            Cli::Prove(args) => args,
+            _ => unreachable!(),
+        };
+        let mut pool = DatabasePool::new(&args.dirs).unwrap();
+        for space in ["bar", "foo"] {
+            pool.open(space).unwrap();
+        }
+
+        let keys : Vec<String> = (0..2).map(|_| {
+            let (payload, _) = prepare_zk_input(&args.dirs, &mut pool, &args.proving, &CommitState::new()).unwrap();
+            assert_eq!(payload.tx_sets, 2);
+            payload.cache_key()
+        }).collect();
+        assert_eq!(keys[0], keys[1]);
+    }
+}
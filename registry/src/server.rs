@@ -0,0 +1,184 @@
+// Minimal HTTP endpoint for submitting builder JSON to the staging area
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+use program::builder::TransactionBuilder;
+use program::Network;
+use crate::{add_builder, builder_stats, load_builders, lock_staging, new_entries, save_builders, verify_builder, DatabasePool, DirArgs};
+use crate::error::{RegistryError, Result};
+use crate::history;
+#[cfg(feature = "async")]
+use crate::prove::{prove_async, ProveArgs};
+#[cfg(feature = "async")]
+use crate::Outcome;
+
+/// Largest request body accepted, anything bigger is answered with 413
+const MAX_BODY: u64 = 8 * 1024 * 1024;
+
+pub fn serve(listen: &str, dirs: &DirArgs, network: Network, max_history: usize) -> Result<()> {
+    dirs.prepare()?;
+    let server = Server::http(listen).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::Other, format!("could not listen on {}: {}", listen, e))
+    })?;
+    println!("Accepting transactions on http://{}", listen);
+
+    #[cfg(feature = "async")]
+    let mut prover = BackgroundProver::new()?;
+    for request in server.incoming_requests() {
+        #[cfg(feature = "async")]
+        let request = match prover.route(request, dirs, network) {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("Request failed: {}", e);
+                continue;
+            }
+        };
+        // requests are handled one at a time so staging updates don't interleave,
+        // one that fails, e.g. a client that went away, doesn't stop the others
+        if let Err(e) = handle_request(request, dirs, network, max_history) {
+            eprintln!("Request failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Proofs started with `POST /prove`, run by `prove_async` so the server
+/// keeps answering while one runs
+#[cfg(feature = "async")]
+struct BackgroundProver {
+    runtime: tokio::runtime::Runtime,
+    running: Option<tokio::task::JoinHandle<Result<Outcome>>>,
+}
+
+#[cfg(feature = "async")]
+impl BackgroundProver {
+    fn new() -> Result<Self> {
+        Ok(BackgroundProver { runtime: tokio::runtime::Runtime::new()?, running: None })
+    }
+
+    /// Handles requests that start or wait on a proof, anything else is
+    /// handed back to be staged
+    fn route(&mut self, request: Request, dirs: &DirArgs, network: Network) -> Result<Option<Request>> {
+        let proving = self.is_running();
+        if *request.method() == Method::Post && request.url() == "/prove" {
+            if proving {
+                respond(request, 409, error_json(String::from("a proof is already running")))?;
+            } else {
+                self.running = Some(self.runtime.spawn(prove_async(ProveArgs::with_network(dirs.clone(), network))));
+                respond(request, 202, "{\"proving\":true}".to_string())?;
+            }
+            return Ok(None);
+        }
+        if proving {
+            // the proof holds the staging lock, waiting for it would stall every request
+            respond(request, 503, error_json(String::from("a proof is running, try again once it's done")))?;
+            return Ok(None);
+        }
+        Ok(Some(request))
+    }
+
+    /// Whether a proof is still running, the outcome of one that finished is logged
+    fn is_running(&mut self) -> bool {
+        match self.running.take() {
+            Some(handle) if !handle.is_finished() => {
+                self.running = Some(handle);
+                true
+            }
+            Some(handle) => {
+                match self.runtime.block_on(handle) {
+                    Ok(Ok(_)) => println!("Proof finished"),
+                    Ok(Err(e)) => eprintln!("Proof failed: {}", e),
+                    Err(e) => eprintln!("Proving task failed: {}", e),
+                }
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+fn handle_request(mut request: Request, dirs: &DirArgs, network: Network, max_history: usize) -> Result<()> {
+    if *request.method() != Method::Post {
+        return respond(request, 405, "{\"error\":\"expected POST\"}".to_string());
+    }
+
+    let mut raw = Vec::new();
+    if let Err(e) = request.as_reader().take(MAX_BODY + 1).read_to_end(&mut raw) {
+        return respond(request, 400, error_json(e.to_string()));
+    }
+    if raw.len() as u64 > MAX_BODY {
+        return respond(request, 413, error_json(format!("request body exceeds {} bytes", MAX_BODY)));
+    }
+
+    // nothing is staged unless every witness verifies against the committed owners
+    match rejected_entries(&raw, dirs, network) {
+        Ok(rejected) if rejected.is_empty() => {}
+        Ok(rejected) => {
+            let body = serde_json::json!({
+                "error": "transactions do not verify against the committed owners",
+                "rejected": rejected,
+            });
+            return respond(request, 400, body.to_string());
+        }
+        Err(e) => return respond(request, 400, error_json(e.to_string())),
+    }
+
+    let result = lock_staging(dirs).and_then(|_lock| {
+        let mut builders = load_builders(dirs)?;
+        let before = builders.clone();
+        add_builder(&mut builders, raw, network, false)?;
+        save_builders(&builders, dirs)?;
+        history::record(dirs, "add", &new_entries(&before, &builders), history::Effect::Staged(&builders),
+                        max_history)?;
+        Ok(builders)
+    });
+
+    match result {
+        Ok(builders) => {
+            let mut registrations = 0;
+            let mut updates = 0;
+            for builder in builders.values() {
+                let (r, u) = builder_stats(builder);
+                registrations += r;
+                updates += u;
+            }
+            let body = serde_json::json!({
+                "spaces": builders.len(),
+                "registrations": registrations,
+                "updates": updates,
+            });
+            respond(request, 200, body.to_string())
+        }
+        Err(e) => respond(request, 400, error_json(e.to_string())),
+    }
+}
+
+/// Transactions in `raw` that don't verify, as `name@space: reason`
+fn rejected_entries(raw: &[u8], dirs: &DirArgs, network: Network) -> Result<Vec<String>> {
+    let builders : BTreeMap<String, TransactionBuilder> = serde_json::from_slice(raw).map_err(|_e| {
+        RegistryError::Validation(String::from("could not parse user tx"))
+    })?;
+    let mut pool = DatabasePool::new(dirs)?;
+    let mut rejected = Vec::new();
+    for (space, builder) in &builders {
+        let results = verify_builder(&mut pool, space, builder, network, false)?;
+        for (entry, result) in builder.transactions.iter().zip(results) {
+            if let Err(e) = result {
+                rejected.push(format!("{}@{}: {}", entry.name, space, e));
+            }
+        }
+    }
+    Ok(rejected)
+}
+
+fn error_json(message: String) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+fn respond(request: Request, status: u16, body: String) -> Result<()> {
+    let header = Header::from_bytes("Content-Type", "application/json").unwrap();
+    request.respond(Response::from_string(body).with_status_code(status).with_header(header))?;
+    Ok(())
+}
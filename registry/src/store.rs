@@ -0,0 +1,56 @@
+// Storage behind a space's committed state. Local `.sdb` files are the only
+// backend today, code written against `RegistryStore` doesn't need to change
+// for one that talks to a remote service.
+
+use spacedb::{Hash, Sha256Hasher};
+use spacedb::db::Database;
+use crate::error::Result;
+
+/// Committed state of a single space. Inserts are held back until `commit`
+/// applies them together, reads only ever see committed state.
+pub trait RegistryStore {
+    fn read_root(&mut self) -> Result<Hash>;
+
+    /// Leaf value of `key`, if it's set
+    fn get(&mut self, key: &Hash) -> Result<Option<Vec<u8>>>;
+
+    fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<()>;
+
+    fn commit(&mut self) -> Result<()>;
+}
+
+/// A space's local database, each read begins a fresh snapshot
+pub struct LocalStore<'a> {
+    db: &'a Database<Sha256Hasher>,
+    pending: Vec<(Hash, Vec<u8>)>,
+}
+
+impl<'a> LocalStore<'a> {
+    pub fn new(db: &'a Database<Sha256Hasher>) -> Self {
+        Self { db, pending: Vec::new() }
+    }
+}
+
+impl RegistryStore for LocalStore<'_> {
+    fn read_root(&mut self) -> Result<Hash> {
+        Ok(self.db.begin_read()?.root()?)
+    }
+
+    fn get(&mut self, key: &Hash) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.begin_read()?.get(key)?)
+    }
+
+    fn insert(&mut self, key: Hash, value: Vec<u8>) -> Result<()> {
+        self.pending.push((key, value));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        let mut tx = self.db.begin_write()?;
+        for (key, value) in self.pending.drain(..) {
+            tx.insert(key, value)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
@@ -1,20 +1,90 @@
 use std::{fs, io};
 use std::collections::HashMap;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use atty::Stream;
 use clap::{Parser, Subcommand};
 use k256::ecdsa::SigningKey;
 use rand_core::OsRng;
-use program::builder::{Transaction, OwnerPublicKey, TransactionBuilder};
+use program::builder::{derive_subspace_key, Transaction, OwnerPublicKey, SignatureScheme, TransactionBuilder};
+use program::guest::{ValidityWindow, WITNESS_FLAG_MEMO, WITNESS_FLAG_WINDOW, WITNESS_TYPE_SCHNORR, WITNESS_TYPE_SIGNATURE, WITNESS_TYPES};
+use program::{name, vectors, Network, Owner, FORMAT_VERSION, SEC1_OWNER_SIZE};
 
 #[derive(Parser)]
 #[command(bin_name = "subs")]
+struct Options {
+    /// Output format, builder JSON is printed as JSON either way
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Shorthand for --format json
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// How public keys and owners are printed by key gen, key inspect, list-keys and builder JSON
+    #[arg(long, global = true, value_enum, default_value_t = OwnerFormat::Xonly)]
+    owner_format: OwnerFormat,
+
+    /// Signature scheme for transfer, renew and bare attach-witness signatures
+    #[arg(long, global = true, value_enum, default_value_t = Scheme::Ecdsa)]
+    scheme: Scheme,
+
+    #[command(subcommand)]
+    command: Cli,
+}
+
+/// How commands print their results. With `json` errors are printed to
+/// stderr as JSON too
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+/// Owners are stored as the 32-byte x-coordinate of a key with an even y,
+/// `sec1` prints the full 33-byte compressed key that stands for
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum OwnerFormat {
+    Xonly,
+    Sec1,
+}
+
+impl OwnerFormat {
+    fn encode(self, owner: &Owner) -> String {
+        match self {
+            OwnerFormat::Xonly => hex::encode(owner),
+            OwnerFormat::Sec1 => hex::encode(owner.to_sec1()),
+        }
+    }
+}
+
+/// `ecdsa` witnesses are `WITNESS_TYPE_SIGNATURE`, `schnorr` ones are
+/// BIP340 signatures over the same message
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum Scheme {
+    Ecdsa,
+    Schnorr,
+}
+
+impl From<Scheme> for SignatureScheme {
+    fn from(scheme: Scheme) -> Self {
+        match scheme {
+            Scheme::Ecdsa => SignatureScheme::Ecdsa,
+            Scheme::Schnorr => SignatureScheme::Schnorr,
+        }
+    }
+}
+
+#[derive(Subcommand)]
 enum Cli {
     /// Key utilities
     #[command(name = "key", subcommand)]
     Key(KeyCommands),
 
+    /// Lists the private keys in a directory and the subspaces they control
+    #[command(name = "list-keys")]
+    ListKeys(ListKeysArgs),
+
     /// Create new subspaces
     #[command(name = "create")]
     Create(CreateArgs),
@@ -26,6 +96,30 @@ enum Cli {
     /// Renewals
     #[command(name = "renew")]
     RenewSubspace(TransferSubspaceArgs),
+
+    /// Rotate the key controlling subspaces
+    #[command(name = "rotate")]
+    RotateSubspace(RotateSubspaceArgs),
+
+    /// Prints the hex message a witness has to sign, for signing offline
+    #[command(name = "sign-request")]
+    SignRequest(SignRequestArgs),
+
+    /// Fills in a witness signed offline after checking it
+    #[command(name = "attach-witness")]
+    AttachWitness(AttachWitnessArgs),
+
+    /// Writes the raw transaction blob built from builder JSON
+    #[command(name = "build")]
+    Build(BuildArgs),
+
+    /// Prints the transaction format version and witness types this binary supports
+    #[command(name = "format-version")]
+    FormatVersion,
+
+    /// Prints deterministic test vectors for the transaction format
+    #[command(name = "test-vectors")]
+    TestVectors(TestVectorsArgs),
 }
 
 #[derive(Subcommand)]
@@ -35,12 +129,29 @@ enum KeyCommands {
     #[command(name = "gen")]
     GenKey {
         #[arg(short = 'C')]
-        c: Option<String>,
+        c: Option<PathBuf>,
+
+        /// Directory to write the key to, defaults to -C
+        #[arg(long)]
+        keys_dir: Option<PathBuf>,
+
+        /// Number of keys to generate
+        #[arg(short = 'n', long, default_value_t = 1)]
+        count: usize,
+
+        /// Print a file,pubkey CSV instead
+        #[arg(long)]
+        csv: bool,
+
+        /// Derive keys from this 32-byte hex seed instead of the OS RNG.
+        /// Anyone with the seed has the keys, only use this for tests
+        #[arg(long)]
+        seed: Option<String>,
     },
 
-    /// Prints the public key of a private key
+    /// Prints the public key of a private key, read from SUBS_PRIVATE_KEY if no path is given
     #[command(name = "inspect")]
-    InspectKey { path: String },
+    InspectKey { path: Option<String> },
 }
 
 #[derive(clap::Args)]
@@ -51,8 +162,31 @@ struct CreateArgs {
     #[arg(short='k', long)]
     private_key: Option<String>,
 
+    /// Derive each subspace key from this master key instead of per-subspace key files
+    #[arg(long, conflicts_with = "private_key")]
+    master: Option<String>,
+
     #[arg(short = 'C')]
-    c: Option<String>,
+    c: Option<PathBuf>,
+
+    /// Directory holding per-subspace private keys, defaults to -C
+    #[arg(long)]
+    keys_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Print the transactions without writing any new private keys
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Hex encoded memo to bind to each transaction
+    #[arg(long)]
+    memo: Option<String>,
+
+    /// Print a summary of what would be registered to stderr
+    #[arg(long)]
+    stats: bool,
 }
 
 #[derive(clap::Args)]
@@ -66,19 +200,144 @@ struct TransferSubspaceArgs {
     #[arg(short='k', long)]
     private_key: Option<String>,
 
+    /// Derive each subspace key from this master key instead of per-subspace key files
+    #[arg(long, conflicts_with = "private_key")]
+    master: Option<String>,
+
     #[arg(short, long)]
     output: Option<String>,
 
+    /// Hex encoded memo covered by the signature
+    #[arg(long)]
+    memo: Option<String>,
+
+    /// First block height the signature is valid at
+    #[arg(long, requires = "valid_until")]
+    valid_from: Option<u32>,
+
+    /// Last block height the signature is valid at
+    #[arg(long, requires = "valid_from")]
+    valid_until: Option<u32>,
+
     #[arg(short = 'C')]
-    c: Option<String>,
+    c: Option<PathBuf>,
+
+    /// Directory holding per-subspace private keys, defaults to -C
+    #[arg(long)]
+    keys_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+
+    /// Leave subspaces without a key file unsigned for another signer, their
+    /// signing preimages are printed to stderr
+    #[arg(long, conflicts_with_all = ["private_key", "master"])]
+    partial: bool,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct RotateSubspaceArgs {
+    subspaces: Option<Vec<String>>,
+
+    /// Private key currently controlling the subspaces
+    #[arg(long)]
+    old_key: String,
+
+    /// Private key taking over the subspaces
+    #[arg(long)]
+    new_key: String,
+
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
 }
 
-fn new_subspace(mut args : CreateArgs) -> Result<(), io::Error> {
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct SignRequestArgs {
+    subspace: String,
+
+    /// Builder JSON holding the unsigned transaction
+    file: String,
+
+    /// Add an unsigned transfer to this hex encoded owner to the file first
+    #[arg(short, long)]
+    address: Option<String>,
+
+    /// Hex encoded memo covered by the signature, only used with --address
+    #[arg(long)]
+    memo: Option<String>,
+
+    /// Network of a newly created file, only used with --address
+    #[arg(long, default_value_t = Network::Mainnet)]
+    network: Network,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct AttachWitnessArgs {
+    subspace: String,
+
+    /// Builder JSON holding the unsigned transaction, updated in place
+    file: String,
+
+    /// Hex encoded witness, a bare 64-byte signature is taken as a signature
+    /// witness of the --scheme type
+    #[arg(long)]
+    witness: String,
+
+    /// Hex encoded public key of the current owner that signed the witness
+    #[arg(long)]
+    signer: String,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct BuildArgs {
+    /// Builder JSON as printed by create/transfer, read from stdin if omitted
+    file: Option<String>,
+
+    /// Space to build, required if the JSON has more than one
+    #[arg(short, long)]
+    space: Option<String>,
+
+    /// Write the blob here instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct ListKeysArgs {
+    /// Directory holding the private keys
+    #[arg(long, default_value = ".")]
+    dir: String,
+}
+
+#[derive(clap::Args)]
+#[command(author, version, about, long_about = None)]
+struct TestVectorsArgs {
+    /// 32-byte hex seed used to derive the keys
+    #[arg(short, long)]
+    seed: Option<String>,
+
+    /// Check the transactions of the zero seed's vectors against the bytes
+    /// pinned in the program crate instead of printing the vectors
+    #[arg(long, conflicts_with = "seed")]
+    check: bool,
+}
+
+fn new_subspace(mut args : CreateArgs, owner_format: OwnerFormat) -> Result<(), io::Error> {
     let subspaces = read_subspaces_input(args.subspaces.take())?;
 
+    let master = args.master.as_ref().map(|path| load_signing_key(path, false)).transpose()?;
+    let env_key = if args.private_key.is_none() && master.is_none() { env_signing_key()? } else { None };
+    let memo = parse_memo(&args.memo)?;
+
     let mut json : HashMap<String, TransactionBuilder> = HashMap::new();
+    let (mut reused, mut generated) = (0, 0);
     for (subspace, space) in subspaces {
-        let wd = get_working_dir(&args.c)?;
+        let wd = get_working_dir(args.keys_dir.as_ref().or(args.c.as_ref()))?;
         let mut c = true;
         let private_key_path = if args.private_key.is_some() {
             c = false;
@@ -87,63 +346,247 @@ fn new_subspace(mut args : CreateArgs) -> Result<(), io::Error> {
             wd.join(format!("{}@{}.priv", subspace, space))
         };
 
-        let signing_key = load_signing_key(private_key_path.to_str().unwrap(), c);
+        let signing_key = if let Some(master) = &master {
+            derive_subspace_key(master, subspace.as_str(), space.as_str()).map_err(|e| {
+                io::Error::new(io::ErrorKind::Other, e)
+            })?
+        } else if let Some(key) = &env_key {
+            reused += 1;
+            key.clone()
+        } else if c && args.dry_run && !private_key_path.exists() {
+            // keep the key in memory only
+            generated += 1;
+            SigningKey::random(&mut OsRng)
+        } else {
+            if private_key_path.exists() { reused += 1 } else { generated += 1 }
+            load_signing_key(&private_key_path, c)?
+        };
         let builder = json.entry(space.clone()).or_insert_with(|| {
-            TransactionBuilder::new()
+            TransactionBuilder::with_network(args.network)
         });
 
-        let entry = Transaction::new(subspace.as_str(), signing_key.owner_public_key());
+        let entry = Transaction::new(subspace.as_str(), signing_key.owner_public_key())
+            .with_memo(memo.clone());
         builder.add(entry, None).map_err(|e| {
             io::Error::new(io::ErrorKind::Other, e.clone())
         })?;
     }
 
-    let str = serde_json::to_string_pretty(&json).map_err(|e| {
-        io::Error::new(io::ErrorKind::Other, e)
-    })?;
+    if args.stats {
+        let total : usize = json.values().map(|b| b.transactions.len()).sum();
+        eprintln!("Subspaces to register:");
+        eprintln!("Total spaces: {}, Total Subspaces: {}", json.len(), total);
+        if master.is_some() {
+            eprintln!("Keys derived from master: {}", total);
+        } else {
+            eprintln!("Existing keys: {}, New keys: {}", reused, generated);
+        }
+    }
 
-    println!("{}", str);
-    Ok(())
+    print_builders(&json, owner_format)
 }
 
-fn transfer_subspace(mut args : TransferSubspaceArgs) -> Result<(), io::Error> {
+fn transfer_subspace(mut args : TransferSubspaceArgs, renew: bool, scheme: Scheme, owner_format: OwnerFormat)
+    -> Result<(), io::Error> {
     let subspaces = read_subspaces_input(args.subspaces.take())?;
+    let master = args.master.as_ref().map(|path| load_signing_key(path, false)).transpose()?;
+    let env_key = if args.private_key.is_none() && master.is_none() { env_signing_key()? } else { None };
+    let memo = parse_memo(&args.memo)?;
+    let window = args.valid_from.zip(args.valid_until)
+        .map(|(valid_from, valid_until)| ValidityWindow { valid_from, valid_until });
     let mut json : HashMap<String, TransactionBuilder> = HashMap::new();
+    let mut unsigned = Vec::new();
 
     for (subspace, space) in subspaces {
-        let wd = get_working_dir(&args.c)?;
+        let wd = get_working_dir(args.keys_dir.as_ref().or(args.c.as_ref()))?;
         let private_key_path = if args.private_key.is_some() {
             PathBuf::from(args.private_key.as_ref().unwrap())
         } else {
             wd.join(format!("{}@{}.priv", subspace, space))
         };
-        let signing_key = load_signing_key(private_key_path.to_str().unwrap(), false);
+        let signing_key = match (&master, &env_key) {
+            (Some(master), _) => Some(derive_subspace_key(master, subspace.as_str(), space.as_str()).map_err(|e| {
+                io::Error::new(io::ErrorKind::Other, e)
+            })?),
+            (None, Some(key)) => Some(key.clone()),
+            (None, None) if args.partial && !private_key_path.exists() => None,
+            (None, None) => Some(load_signing_key(&private_key_path, false)?),
+        };
 
         let builder = json.entry(space.clone()).or_insert_with(|| {
-            TransactionBuilder::new()
+            let mut builder = TransactionBuilder::with_network(args.network);
+            builder.set_scheme(scheme.into());
+            builder
         });
 
-        let transfer_addr = hex::decode(args.address.as_str()).map_err(|_e| {
+        let transfer_addr = parse_owner(args.address.as_str()).ok_or_else(|| {
             io::Error::new(io::ErrorKind::InvalidInput, "invalid address")
         })?;
 
-        let entry = Transaction::new(subspace.as_str(), transfer_addr.as_slice().try_into()
-            .map_err(|_e| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?);
+        let mut entry = Transaction::new(subspace.as_str(), transfer_addr).with_memo(memo.clone());
+        entry.window = window;
 
-        builder.add(entry, Some((space.as_str(), signing_key))).map_err(|e| {
+        let result = match signing_key {
+            Some(signing_key) if renew => builder.renew(entry, (space.as_str(), signing_key)),
+            Some(signing_key) => builder.add(entry, Some((space.as_str(), signing_key))),
+            // placeholder for another signer, completed with attach-witness
+            None => builder.add(entry, None)
+                .and_then(|_| builder.signing_preimage(space.as_str(), subspace.as_str()))
+                .map(|preimage| unsigned.push((format!("{}@{}", subspace, space), preimage))),
+        };
+        result.map_err(|e| {
             io::Error::new(io::ErrorKind::Other, e.clone())
         })?;
     }
 
-    let str = serde_json::to_string_pretty(&json).map_err(|e| {
+    if !unsigned.is_empty() {
+        eprintln!("Unsigned, no key file (sign the preimage and use \"subs attach-witness\"):");
+        for (name, preimage) in unsigned {
+            eprintln!("{} {}", name, hex::encode(preimage));
+        }
+    }
+    print_builders(&json, owner_format)
+}
+
+fn rotate_subspace(mut args : RotateSubspaceArgs, scheme: Scheme, owner_format: OwnerFormat)
+    -> Result<(), io::Error> {
+    if scheme != Scheme::Ecdsa {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "rotations are only signed with ecdsa"));
+    }
+    let subspaces = read_subspaces_input(args.subspaces.take())?;
+    let mut json : HashMap<String, TransactionBuilder> = HashMap::new();
+
+    for (subspace, space) in subspaces {
+        let old_key = load_signing_key(args.old_key.as_str(), false)?;
+        let new_key = load_signing_key(args.new_key.as_str(), false)?;
+
+        let builder = json.entry(space.clone()).or_insert_with(|| {
+            TransactionBuilder::with_network(args.network)
+        });
+
+        builder.rotate(subspace.as_str(), space.as_str(), old_key, new_key).map_err(|e| {
+            io::Error::new(io::ErrorKind::Other, e.clone())
+        })?;
+    }
+
+    print_builders(&json, owner_format)
+}
+
+fn sign_request(args: SignRequestArgs, format: Format) -> Result<(), io::Error> {
+    let (subspace, space) = verify_name(&args.subspace)?;
+    let path = Path::new(args.file.as_str());
+    let mut builders = if path.exists() { load_builders(path)? } else { HashMap::new() };
+
+    if let Some(address) = &args.address {
+        let owner = parse_owner(address.as_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?;
+        let entry = Transaction::new(subspace.as_str(), owner).with_memo(parse_memo(&args.memo)?);
+        builders.entry(space.clone())
+            .or_insert_with(|| TransactionBuilder::with_network(args.network))
+            .add(entry, None)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        save_builders(path, &builders)?;
+    }
+
+    let builder = builders.get_mut(&space).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no transactions for @{}", space))
+    })?;
+    let preimage = builder.signing_preimage(space.as_str(), subspace.as_str())
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))?;
+    match format {
+        Format::Text => println!("{}", hex::encode(preimage)),
+        Format::Json => println!("{}", serde_json::json!({ "preimage": hex::encode(preimage) })),
+    }
+    Ok(())
+}
+
+fn attach_witness(args: AttachWitnessArgs, scheme: Scheme) -> Result<(), io::Error> {
+    let (subspace, space) = verify_name(&args.subspace)?;
+    let path = Path::new(args.file.as_str());
+    let mut builders = load_builders(path)?;
+
+    let mut witness = hex::decode(args.witness.as_str()).map_err(|_e| {
+        io::Error::new(io::ErrorKind::InvalidInput, "witness must be hex")
+    })?;
+    if witness.len() == 64 {
+        witness.insert(0, match scheme {
+            Scheme::Ecdsa => WITNESS_TYPE_SIGNATURE,
+            Scheme::Schnorr => WITNESS_TYPE_SCHNORR,
+        });
+    }
+    let signer = parse_owner(args.signer.as_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid signer"))?;
+
+    let builder = builders.get_mut(&space).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no transactions for @{}", space))
+    })?;
+    builder.attach_witness(space.as_str(), subspace.as_str(), witness, &signer)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    save_builders(path, &builders)
+}
+
+fn load_builders(path: &Path) -> Result<HashMap<String, TransactionBuilder>, io::Error> {
+    let raw = fs::read(path)?;
+    serde_json::from_slice(raw.as_slice()).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("could not parse builder json: {}", e))
+    })
+}
+
+/// Prints builder JSON, with owners as SEC1 keys if asked to. Either form
+/// is read back the same way
+fn print_builders(json: &HashMap<String, TransactionBuilder>, owner_format: OwnerFormat)
+    -> Result<(), io::Error> {
+    let mut value = serde_json::to_value(json).map_err(|e| {
         io::Error::new(io::ErrorKind::Other, e)
     })?;
+    if owner_format == OwnerFormat::Sec1 {
+        for builder in value.as_object_mut().into_iter().flat_map(|spaces| spaces.values_mut()) {
+            let transactions = builder.get_mut("transactions").and_then(|t| t.as_array_mut());
+            for tx in transactions.into_iter().flatten() {
+                if let Some(owner) = tx.get_mut("owner") {
+                    let sec1 = owner.as_str().and_then(parse_owner).map(|o| owner_format.encode(&o));
+                    if let Some(sec1) = sec1 {
+                        *owner = serde_json::Value::String(sec1);
+                    }
+                }
+            }
+        }
+    }
 
+    let str = serde_json::to_string_pretty(&value).map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, e)
+    })?;
     println!("{}", str);
     Ok(())
 }
 
+/// Parses an owner given as a hex x-only key or compressed SEC1 key
+fn parse_owner(owner: &str) -> Option<Owner> {
+    let raw = hex::decode(owner).ok()?;
+    match raw.len() {
+        SEC1_OWNER_SIZE => Owner::from_sec1(&raw),
+        _ => Owner::try_from(raw.as_slice()).ok(),
+    }
+}
+
+fn save_builders(path: &Path, builders: &HashMap<String, TransactionBuilder>) -> Result<(), io::Error> {
+    let str = serde_json::to_string_pretty(builders).map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, e)
+    })?;
+    fs::write(path, str)
+}
+
+fn parse_memo(memo: &Option<String>) -> Result<Vec<u8>, io::Error> {
+    match memo {
+        Some(memo) => hex::decode(memo.as_str()).map_err(|_e| {
+            io::Error::new(io::ErrorKind::InvalidInput, "memo must be hex")
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
 fn read_subspaces_input(mut subspaces: Option<Vec<String>>) -> Result<Vec<(String, String)>, io::Error> {
+    let source = if subspaces.is_some() { "argument" } else { "line" };
     if subspaces.is_none() {
         if !atty::is(Stream::Stdin) {
             let mut input = String::new();
@@ -157,14 +600,22 @@ fn read_subspaces_input(mut subspaces: Option<Vec<String>>) -> Result<Vec<(Strin
     }
     let subspaces = subspaces.unwrap();
     let mut resolved = Vec::new();
-    for sub in subspaces {
-        let (subspace, space) = verify_name(&sub)?;
+    // position each name was first given at, to point at both inputs of a conflict
+    let mut seen : HashMap<(String, String), usize> = HashMap::new();
+    for (i, sub) in subspaces.iter().enumerate() {
+        let (subspace, space) = verify_name(sub).map_err(|e| {
+            io::Error::new(e.kind(), format!("{} {} ({}): {}", source, i + 1, sub, e))
+        })?;
+        if let Some(first) = seen.insert((subspace.clone(), space.clone()), i + 1) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("{}@{} is given twice, {} {} and {}", subspace, space, source, first, i + 1)));
+        }
         resolved.push((subspace, space));
     }
     Ok(resolved)
 }
 
-fn get_working_dir(c : &Option<String>) -> Result<PathBuf, io::Error> {
+fn get_working_dir(c : Option<&PathBuf>) -> Result<PathBuf, io::Error> {
     let mut path_prefix = PathBuf::new();
     if let Some(output) = c {
         path_prefix.push(output);
@@ -174,7 +625,8 @@ fn get_working_dir(c : &Option<String>) -> Result<PathBuf, io::Error> {
 
         let metadata = fs::metadata(&path_prefix)?;
         if !metadata.is_dir() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Path is not a directory"));
+            return Err(io::Error::new(io::ErrorKind::Other,
+                format!("{} is not a directory", path_prefix.display())));
         }
     } else {
         path_prefix.push(".");
@@ -182,25 +634,46 @@ fn get_working_dir(c : &Option<String>) -> Result<PathBuf, io::Error> {
     Ok(path_prefix)
 }
 
-fn run() -> Result<(), io::Error> {
-    let cmd = Cli::parse();
+fn run(cmd: Cli, format: Format, scheme: Scheme, owner_format: OwnerFormat) -> Result<(), io::Error> {
     match cmd {
+        Cli::ListKeys(args) => {
+            list_keys(args, format, owner_format)
+        },
         Cli::Create(args) => {
-            new_subspace(args)
+            new_subspace(args, owner_format)
         },
         Cli::TransferSubspace(args) => {
-            transfer_subspace(args)
+            transfer_subspace(args, false, scheme, owner_format)
         },
         Cli::RenewSubspace(args) => {
-            transfer_subspace(args)
+            transfer_subspace(args, true, scheme, owner_format)
+        },
+        Cli::RotateSubspace(args) => {
+            rotate_subspace(args, scheme, owner_format)
+        },
+        Cli::SignRequest(args) => {
+            sign_request(args, format)
+        },
+        Cli::AttachWitness(args) => {
+            attach_witness(args, scheme)
+        },
+        Cli::Build(args) => {
+            build(args)
+        },
+        Cli::FormatVersion => {
+            format_version(format)
+        },
+        Cli::TestVectors(args) => {
+            test_vectors(args)
         },
         Cli::Key(args) => {
            match args {
-               KeyCommands::GenKey{c} => {
-                gen_key(c)
+               KeyCommands::GenKey{c, keys_dir, count, csv, seed} => {
+                let seed = seed.as_deref().map(parse_seed).transpose()?;
+                gen_keys(keys_dir.or(c), count, csv, format, owner_format, seed)
                },
                KeyCommands::InspectKey { path } => {
-                inspect_key(path)
+                inspect_key(path, format, owner_format)
                }
            }
         }
@@ -208,92 +681,272 @@ fn run() -> Result<(), io::Error> {
 
 }
 
-fn inspect_key(path: String) -> Result<(), io::Error> {
-    let key = fs::read(path).map_err(|e| {
-        io::Error::new(io::ErrorKind::Other, e)
+fn build(args: BuildArgs) -> Result<(), io::Error> {
+    let raw = match args.file {
+        Some(file) => fs::read(file)?,
+        None => {
+            let mut raw = Vec::new();
+            io::stdin().read_to_end(&mut raw)?;
+            raw
+        }
+    };
+    let mut builders : HashMap<String, TransactionBuilder> = serde_json::from_slice(raw.as_slice()).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("could not parse builder json: {}", e))
     })?;
 
-    let key = SigningKey::from_slice(key.as_slice()).map_err(|_e| {
-        io::Error::new(io::ErrorKind::InvalidData, "Invalid private key")
+    let space = match args.space {
+        Some(space) => space,
+        None if builders.len() == 1 => builders.keys().next().unwrap().clone(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "json has more than one space, pick one with --space")),
+    };
+    let builder = builders.remove(&space).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no transactions for @{}", space))
     })?;
 
-    let pub_key = key.owner_public_key();
-    let pub_key_hex = hex::encode(&pub_key);
-    println!("Public key: {}", pub_key_hex);
+    let blob = builder.build(space.as_str()).map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, e)
+    })?;
+    match args.output {
+        Some(path) => fs::write(path, blob),
+        None => io::stdout().write_all(&blob),
+    }
+}
+
+fn format_version(format: Format) -> Result<(), io::Error> {
+    if format == Format::Json {
+        let witness_types : HashMap<&str, u8> = WITNESS_TYPES.iter().map(|(kind, name)| (*name, *kind)).collect();
+        println!("{}", serde_json::json!({
+            "format_version": FORMAT_VERSION,
+            "networks": {
+                Network::Mainnet.to_string(): Network::Mainnet as u8,
+                Network::Testnet.to_string(): Network::Testnet as u8,
+            },
+            "witness_types": witness_types,
+            "memo_flag": WITNESS_FLAG_MEMO,
+            "window_flag": WITNESS_FLAG_WINDOW,
+        }));
+        return Ok(());
+    }
+    println!("Format version: {}", FORMAT_VERSION);
+    println!("Networks: {} ({:#04x}), {} ({:#04x})",
+             Network::Mainnet, Network::Mainnet as u8, Network::Testnet, Network::Testnet as u8);
+    println!("Witness types:");
+    for (kind, name) in WITNESS_TYPES {
+        println!("  {:#04x} {}", kind, name);
+    }
+    println!("  {:#04x} memo flag", WITNESS_FLAG_MEMO);
+    println!("  {:#04x} validity window flag", WITNESS_FLAG_WINDOW);
     Ok(())
 }
 
-fn gen_key(c: Option<String>) -> Result<(), io::Error> {
-    let key = SigningKey::random(&mut OsRng);
-    let pub_key = key.owner_public_key();
-    let pub_key_hex = hex::encode(&pub_key);
-    let path = get_working_dir(&c)?.join(format!("k-{}.priv", &pub_key_hex[0..8]));
-    fs::write(path.to_str().unwrap(), key.to_bytes()).map_err(|e| {
+fn test_vectors(args: TestVectorsArgs) -> Result<(), io::Error> {
+    if args.check {
+        let checked = vectors::check_pinned().map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        })?;
+        println!("{} vectors match the pinned bytes", checked);
+        return Ok(());
+    }
+    let seed = args.seed.as_deref().map(parse_seed).transpose()?.unwrap_or([0u8; 32]);
+
+    let vectors = vectors::generate(&seed).map_err(|e| {
+        io::Error::new(io::ErrorKind::Other, e)
+    })?;
+
+    let str = serde_json::to_string_pretty(&vectors).map_err(|e| {
         io::Error::new(io::ErrorKind::Other, e)
     })?;
 
-    println!("Generated {}", path.to_str().unwrap());
-    println!("Public key: {}", pub_key_hex);
+    println!("{}", str);
     Ok(())
 }
 
-fn main() {
-    run().unwrap_or_else(|e| {
-        eprintln!("{}", e);
-        std::process::exit(1);
-    });
+fn parse_seed(seed: &str) -> Result<[u8; 32], io::Error> {
+    hex::decode(seed).ok()
+        .and_then(|s| s.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seed must be 32 bytes hex"))
 }
 
-fn load_signing_key(path: &str, create: bool) -> SigningKey {
-    if let Ok(key) = std::fs::read(path) {
-        return SigningKey::from_slice(key.as_slice()).unwrap_or_else(|e| {
-            eprintln!("Invalid private key: {}", e);
-            std::process::exit(1);
-        })
-    }
-
-    if !create {
-        eprintln!("Private key not found at: {}", path);
-        std::process::exit(1);
+fn inspect_key(path: Option<String>, format: Format, owner_format: OwnerFormat) -> Result<(), io::Error> {
+    let key = match path {
+        Some(path) => {
+            let key = fs::read(path).map_err(|e| {
+                io::Error::new(io::ErrorKind::Other, e)
+            })?;
+            SigningKey::from_slice(key.as_slice()).map_err(|_e| {
+                io::Error::new(io::ErrorKind::InvalidData, "Invalid private key")
+            })?
+        }
+        None => env_signing_key()?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput,
+                format!("no key path given and {} is not set", PRIVATE_KEY_ENV))
+        })?,
+    };
+
+    let pub_key_hex = owner_format.encode(&key.owner_public_key());
+    match format {
+        Format::Text => println!("Public key: {}", pub_key_hex),
+        Format::Json => println!("{}", serde_json::json!({ "public_key": pub_key_hex })),
     }
-
-   let key = SigningKey::random(&mut OsRng);
-   fs::write(path, key.to_bytes()).unwrap_or_else(|e| {
-            eprintln!("Failed to write private key: {}", e);
-            std::process::exit(1);
-   });
-   key
+    Ok(())
 }
 
-fn verify_name(subspace: &str) -> Result<(String, String), io::Error> {
-    let (subspace, space) = parse_name(subspace).ok_or_else(|| {
-        io::Error::new(io::ErrorKind::InvalidInput, "Invalid subspace name")
+/// Lists every `.priv` file in the directory with the public key it holds.
+/// Files named `<subspace>@<space>.priv` are listed with the subspace they
+/// control, anything else such as `k-<fingerprint>.priv` without one
+fn list_keys(args: ListKeysArgs, format: Format, owner_format: OwnerFormat) -> Result<(), io::Error> {
+    let mut paths = Vec::new();
+    let entries = fs::read_dir(&args.dir).map_err(|e| {
+        io::Error::new(e.kind(), format!("could not read {}: {}", args.dir, e))
     })?;
-
-    if !is_valid_label(space.as_str()) {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput,
-            format!("Invalid space name @{}", space)));
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "priv") {
+            paths.push(path);
+        }
     }
-    if !is_valid_label(subspace.as_str()) {
-        return Err(io::Error::new(io::ErrorKind::InvalidInput,
-            format!("Invalid subspace {}", subspace)));
+    paths.sort();
+
+    for path in paths {
+        let subspace = path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(name::parse_name)
+            .map(|(subspace, space)| format!("{}@{}", subspace, space));
+        let public_key = load_signing_key(&path, false)
+            .map(|key| owner_format.encode(&key.owner_public_key()));
+
+        match (format, public_key) {
+            (Format::Json, Ok(public_key)) => println!("{}", serde_json::json!({
+                "path": path.to_string_lossy(),
+                "subspace": subspace,
+                "public_key": public_key,
+            })),
+            (Format::Json, Err(e)) => println!("{}", serde_json::json!({
+                "path": path.to_string_lossy(),
+                "subspace": subspace,
+                "error": e.to_string(),
+            })),
+            (Format::Text, public_key) => {
+                let public_key = public_key.unwrap_or_else(|e| e.to_string());
+                println!("{}\t{}\t{}", path.display(), subspace.as_deref().unwrap_or("-"), public_key);
+            }
+        }
     }
+    Ok(())
+}
 
-    Ok((subspace, space))
+/// Generates `count` keys, derived from `seed` the same way as the test
+/// vectors if given so the output is reproducible
+fn gen_keys(c: Option<PathBuf>, count: usize, csv: bool, format: Format, owner_format: OwnerFormat,
+            seed: Option<[u8; 32]>) -> Result<(), io::Error> {
+    if csv && format == Format::Json {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--csv can't be used with JSON output"));
+    }
+    let wd = get_working_dir(c.as_ref())?;
+    if csv {
+        println!("file,pubkey");
+    }
+    for i in 0..count {
+        let key = match &seed {
+            Some(seed) => vectors::derive_key(seed, i as u32),
+            None => SigningKey::random(&mut OsRng),
+        };
+        let (path, pub_key) = gen_key(&wd, key)?;
+        let fingerprint = &hex::encode(pub_key)[..KEY_FINGERPRINT_SIZE];
+        let pub_key_hex = owner_format.encode(&pub_key);
+        if csv {
+            println!("{},{}", path.display(), pub_key_hex);
+        } else if format == Format::Json {
+            println!("{}", serde_json::json!({
+                "path": path.to_string_lossy(),
+                "public_key": pub_key_hex,
+                "fingerprint": fingerprint,
+            }));
+        } else {
+            println!("Generated {}", path.display());
+            println!("Public key: {}", pub_key_hex);
+        }
+    }
+    Ok(())
 }
 
-fn is_valid_label(s: &str) -> bool {
-    s.chars().all(|c| c.is_ascii_alphabetic() && c.is_lowercase())
+/// Hex characters of the public key used to name key files
+const KEY_FINGERPRINT_SIZE: usize = 8;
+
+/// Writes the key its owner signs with, so the file can be used to sign
+/// offline as is
+fn gen_key(wd: &Path, key: SigningKey) -> Result<(PathBuf, Owner), io::Error> {
+    let key = key.owner_signing_key();
+    let pub_key = key.owner_public_key();
+    let path = wd.join(format!("k-{}.priv", &hex::encode(pub_key)[..KEY_FINGERPRINT_SIZE]));
+    // never overwrite an existing key on a prefix collision
+    fs::OpenOptions::new().write(true).create_new(true).open(&path)
+        .and_then(|mut file| file.write_all(&key.to_bytes()))
+        .map_err(|e| {
+            io::Error::new(io::ErrorKind::Other, format!("could not write {}: {}", path.display(), e))
+        })?;
+    Ok((path, pub_key))
 }
 
-fn parse_name(subspace: &str) -> Option<(String, String)> {
-    let mut parts = subspace.split('@');
-    if let (Some(label), Some(space)) = (parts.next(), parts.next()) {
-        if parts.next().is_some() {
-            return None;
+fn main() {
+    let options = Options::parse();
+    let format = if options.json { Format::Json } else { options.format };
+    run(options.command, format, options.scheme, options.owner_format).unwrap_or_else(|e| {
+        if format == Format::Json {
+            let error = serde_json::json!({
+                "error": e.to_string(),
+                "kind": format!("{:?}", e.kind()),
+            });
+            eprintln!("{}", error);
+        } else {
+            eprintln!("{}", e);
         }
-        return Some((label.to_string(), space.to_string()))
+        std::process::exit(1);
+    });
+}
+
+/// Hex encoded private key used when no key path is given
+const PRIVATE_KEY_ENV: &str = "SUBS_PRIVATE_KEY";
+
+/// Reads the private key from `SUBS_PRIVATE_KEY` if it's set. The value is
+/// never included in errors.
+fn env_signing_key() -> Result<Option<SigningKey>, io::Error> {
+    let value = match std::env::var(PRIVATE_KEY_ENV) {
+        Ok(value) => value,
+        Err(std::env::VarError::NotPresent) => return Ok(None),
+        Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{} is not valid unicode", PRIVATE_KEY_ENV))),
+    };
+    let raw = hex::decode(value.trim()).map_err(|_e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not valid hex", PRIVATE_KEY_ENV))
+    })?;
+    let key = SigningKey::from_slice(raw.as_slice()).map_err(|_e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a valid private key", PRIVATE_KEY_ENV))
+    })?;
+    Ok(Some(key))
+}
+
+fn load_signing_key(path: impl AsRef<Path>, create: bool) -> Result<SigningKey, io::Error> {
+    let path = path.as_ref();
+    if let Ok(key) = std::fs::read(path) {
+        return SigningKey::from_slice(key.as_slice()).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid private key: {}", e))
+        });
     }
 
-    None
+    if !create {
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("Private key not found at: {}", path.display())));
+    }
+
+   let key = SigningKey::random(&mut OsRng);
+   fs::write(path, key.to_bytes()).map_err(|e| {
+       io::Error::new(e.kind(), format!("Failed to write private key: {}", e))
+   })?;
+   Ok(key)
+}
+
+fn verify_name(subspace: &str) -> Result<(String, String), io::Error> {
+    name::verify_name(subspace).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
 }